@@ -0,0 +1,32 @@
+// シナリオファイルを再生して、送信されたキーストロークとクリップボードへの書き込みを表示する。
+use clap::Parser;
+use main_logic::simulate::{self, Scenario};
+
+#[derive(Parser, Debug)]
+#[command(author, version, about, long_about = None)]
+struct Args {
+    /// 再生するシナリオファイル（TOML）
+    scenario: String,
+}
+
+fn main() {
+    let args = Args::parse();
+    let scenario = match Scenario::load(&args.scenario) {
+        Ok(scenario) => scenario,
+        Err(e) => {
+            eprintln!(
+                "❌  シナリオファイル {} を読み込めませんでした。({e})",
+                args.scenario
+            );
+            std::process::exit(1);
+        }
+    };
+    println!("🎬  シナリオ {} を再生します。", args.scenario);
+    match simulate::run(&scenario) {
+        Ok(report) => report.print(),
+        Err(e) => {
+            eprintln!("❌  シナリオの再生を中断しました。({e})");
+            std::process::exit(1);
+        }
+    }
+}
@@ -0,0 +1,144 @@
+use once_cell::unsync::*;
+use std::ops::{Deref, DerefMut};
+use std::sync::{Arc, Mutex, MutexGuard};
+
+// OSのクリップボードを操作するバックエンド
+// 読み書きはopenしてからcloseするまでの間に行うこと。
+pub trait ClipboardBackend: Send {
+    // クリップボードを開いて他のプロセスから操作できないようにする
+    fn open(&mut self) -> bool;
+    fn close(&mut self);
+    fn get_text(&mut self) -> Option<String>;
+    fn set_text(&mut self, text: &str);
+    // 機密の内容を、クリップボードの履歴や監視しているアプリケーションに渡さないよう指定して書き込む
+    fn set_sensitive_text(&mut self, text: &str);
+    fn clear(&mut self);
+    // クリップボードが変更されるたびに増加する値
+    fn sequence_number(&self) -> u32;
+    // パスワードマネージャーなどが、クリップボードの内容を機密として指定しているか
+    fn is_sensitive(&mut self) -> bool;
+}
+
+static mut CLIPBOARD_BACKEND: Lazy<Mutex<Box<dyn ClipboardBackend>>> =
+    Lazy::new(|| Mutex::new(crate::platform::default_clipboard_backend()));
+
+pub fn set_clipboard_backend(backend: Box<dyn ClipboardBackend>) {
+    let mut b = unsafe { CLIPBOARD_BACKEND.lock().unwrap() };
+    *b = backend;
+}
+
+// 開いている間はバックエンドを占有し、Dropで閉じる。
+pub struct ClipboardLock {
+    backend: MutexGuard<'static, Box<dyn ClipboardBackend>>,
+}
+impl ClipboardLock {
+    pub fn open() -> Self {
+        let mut backend = unsafe { CLIPBOARD_BACKEND.lock().unwrap() };
+        backend.open();
+        ClipboardLock { backend }
+    }
+}
+impl Deref for ClipboardLock {
+    type Target = Box<dyn ClipboardBackend>;
+    fn deref(&self) -> &Self::Target {
+        &self.backend
+    }
+}
+impl DerefMut for ClipboardLock {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        &mut self.backend
+    }
+}
+impl Drop for ClipboardLock {
+    fn drop(&mut self) {
+        self.backend.close();
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ClipboardWrite {
+    pub text: String,
+    // 機密として書き込まれた場合はtrue
+    pub sensitive: bool,
+}
+
+#[derive(Debug, Default)]
+pub struct MemoryClipboardState {
+    pub text: Option<String>,
+    pub is_open: bool,
+    pub sequence: u32,
+    pub sensitive: bool,
+    // ペースト処理によって書き込まれた内容の履歴
+    pub writes: Vec<ClipboardWrite>,
+}
+
+// OSのクリップボードを使用しないバックエンド
+// cloneしたものは状態を共有するので、片方をバックエンドとして登録し、もう片方から中身を確認できる。
+#[derive(Clone, Default)]
+pub struct MemoryClipboard {
+    state: Arc<Mutex<MemoryClipboardState>>,
+}
+impl MemoryClipboard {
+    pub fn new() -> Self {
+        MemoryClipboard::default()
+    }
+    // 他のアプリケーションがクリップボードにコピーした状況を再現する
+    pub fn copy_from_outside(&self, text: &str) {
+        let mut state = self.state.lock().unwrap();
+        state.text = Some(text.to_owned());
+        state.sensitive = false;
+        state.sequence += 1;
+    }
+    // パスワードマネージャーが機密としてコピーした状況を再現する
+    pub fn copy_secret_from_outside(&self, text: &str) {
+        self.copy_from_outside(text);
+        self.state.lock().unwrap().sensitive = true;
+    }
+    pub fn state(&self) -> MutexGuard<'_, MemoryClipboardState> {
+        self.state.lock().unwrap()
+    }
+    fn write(&mut self, text: &str, sensitive: bool) {
+        let mut state = self.state.lock().unwrap();
+        state.text = Some(text.to_owned());
+        state.writes.push(ClipboardWrite {
+            text: text.to_owned(),
+            sensitive,
+        });
+        state.sensitive = sensitive;
+        state.sequence += 1;
+    }
+}
+impl ClipboardBackend for MemoryClipboard {
+    fn open(&mut self) -> bool {
+        let mut state = self.state.lock().unwrap();
+        if state.is_open {
+            return false;
+        }
+        state.is_open = true;
+        true
+    }
+    fn close(&mut self) {
+        self.state.lock().unwrap().is_open = false;
+    }
+    fn get_text(&mut self) -> Option<String> {
+        self.state.lock().unwrap().text.clone()
+    }
+    fn set_text(&mut self, text: &str) {
+        self.write(text, false);
+    }
+    fn set_sensitive_text(&mut self, text: &str) {
+        self.write(text, true);
+    }
+    fn clear(&mut self) {
+        let mut state = self.state.lock().unwrap();
+        state.text = None;
+        state.sensitive = false;
+        state.sequence += 1;
+    }
+    fn sequence_number(&self) -> u32 {
+        self.state.lock().unwrap().sequence
+    }
+    fn is_sensitive(&mut self) -> bool {
+        self.state.lock().unwrap().sensitive
+    }
+}
@@ -3,6 +3,10 @@ use multiline_parser_pluginlib::{
     plugin::{self, MasterConfig, PluginActivateState, PluginManager},
     result::EncodedString,
 };
+use crate::filter::CopyFilter;
+use crate::queue::{QueueLimit, DEFAULT_REGISTER};
+use crate::secret::SensitiveConfig;
+use crate::splitter::{build_splitter, LineEnding, RecordFormat, SplitRule, Whitespace};
 use toolbox::config_loader::*;
 
 pub fn plugin_about(pm: &mut PluginManager, plugin_name: &str) -> (String, PluginActivateState) {
@@ -28,9 +32,36 @@ pub fn init() -> (RunMode, Config) {
         std::process::exit(0);
     }
     let mut mode = args.configure(RunMode::default());
-    let config: Config = ConfigLoader::load_file(&get_config_path());
+    let mut config: Config = ConfigLoader::load_file(&get_config_path());
+    if let Err(e) = config.check() {
+        println!("❌  設定ファイル {} が不正です。({e})", get_config_path());
+        std::process::exit(1);
+    }
     mode.set_config(config.clone());
+    // 復元するかを標準入力で問い合わせている間にキーボードをフックしないよう、バックエンドの初期化より先に行う
+    crate::default::open_queue_journal(&config);
+    crate::platform::configure(&config);
+    crate::hook::sethook();
     crate::default::eh_init();
+    let register = mode.get_register_name();
+    if let Some(path) = &args.load {
+        if let Err(e) = crate::snapshot::load_register(path, &register) {
+            println!("❌  {path} から復元できませんでした。({e})");
+        }
+    }
+    if let Some(path) = &args.import {
+        if let Err(e) = crate::importer::import_file(path, &mode) {
+            println!("❌  {path} を取り込めませんでした。({e})");
+        }
+    }
+    if let Some(path) = &args.export {
+        if let Err(e) = crate::snapshot::export_register(path, &register) {
+            println!("❌  {path} に書き出せませんでした。({e})");
+        }
+    }
+    if args.inspect {
+        crate::inspector::spawn_inspector();
+    }
     (mode, config)
 }
 
@@ -53,6 +84,18 @@ struct CommandLineArgs {
     /// モディファイア(プラグイン)の一覧を表示します
     #[arg(long, default_value_t = false)]
     installed_modifiers: bool,
+    /// 起動時にキューのインスペクタを開きます（CTRL+ALT+Iでも開けます）
+    #[arg(long, default_value_t = false)]
+    inspect: bool,
+    /// 起動時にファイルの内容をキューに取り込みます（-で標準入力から）
+    #[arg(long, value_name = "PATH")]
+    import: Option<String>,
+    /// 起動時にスナップショット（JSON/CSV）からキューを復元します
+    #[arg(long, value_name = "PATH")]
+    load: Option<String>,
+    /// 起動時（復元・取り込みの後）のキューをスナップショット（JSON/CSV）に書き出します
+    #[arg(long, value_name = "PATH")]
+    export: Option<String>,
 }
 
 fn read_dir<P: AsRef<std::path::Path>>(path: P) -> std::io::Result<Vec<String>> {
@@ -106,6 +149,78 @@ pub struct Config {
     pub max_line_length: usize,
     pub text_modifiers_hot_reload: bool,
     pub text_modifiers: Option<Vec<String>>,
+    // コピー元にするセレクション（X11のみ）
+    #[serde(default)]
+    pub capture_selection: CaptureSelection,
+    // キーボードエミュレーションで文字を入力する際のキー配列（uinputのみ）
+    #[serde(default)]
+    pub keyboard_layout: KeyboardLayout,
+    // キーボードを占有して、コンボキーをアプリケーションに渡さないようにする（evdevのみ）
+    #[serde(default = "default_grab_keyboard")]
+    pub grab_keyboard: bool,
+    // キューを保存するジャーナルファイル。指定しない場合はメモリ上にのみ保持する。
+    #[serde(default)]
+    pub queue_journal: Option<String>,
+    // 起動時に前回のキューを復元するか
+    #[serde(default)]
+    pub restore_queue: RestoreQueue,
+    // デフォルトレジスタ以外のレジスタ名（CTRL+ALT+Rで切り替える）
+    #[serde(default)]
+    pub registers: Vec<String>,
+    // ペーストする行の順番（CTRL+ALT+Oで切り替える）
+    #[serde(default)]
+    pub queue_order: QueueOrder,
+    // コピーしたテキストの分割方法（CTRL+ALT+Lで次のコピーだけ切り替える）
+    #[serde(default)]
+    pub split_rule: SplitRule,
+    // split_rule = "regex" の区切り
+    #[serde(default)]
+    pub split_regex: String,
+    // split_rule = "width" の文字数
+    #[serde(default = "default_split_width")]
+    pub split_width: usize,
+    // コピーしたデータをTSV/CSVとして解析し、1回のペーストで1レコードを入力する
+    #[serde(default)]
+    pub record_format: RecordFormat,
+    // レコードモードで各フィールドの後に送信するキーシーケンス
+    #[serde(default = "default_field_separator_key")]
+    pub field_separator_key: String,
+    // コピーした表から残す列（1始まり）。空の場合はすべての列
+    #[serde(default)]
+    pub select_columns: Vec<usize>,
+    // コピーした表の行と列を入れ替える
+    #[serde(default)]
+    pub transpose: bool,
+    // 行末の改行の扱い
+    #[serde(default)]
+    pub line_ending: LineEnding,
+    // 各行の前後や連続した空白の扱い
+    #[serde(default)]
+    pub whitespace: Whitespace,
+    // コピーした行のうちキューに積まないもの
+    #[serde(default)]
+    pub copy_filter: CopyFilter,
+    // キューに保持する行数・バイト数と、コピーする1行の文字数の上限
+    #[serde(default)]
+    pub queue_limit: QueueLimit,
+    // CTRL+ALT+Fで取り込むファイル
+    #[serde(default)]
+    pub import_path: Option<String>,
+    // CTRL+ALT+Eで書き出し、CTRL+ALT+SHIFT+Eで復元するスナップショット
+    #[serde(default)]
+    pub snapshot_path: Option<String>,
+    // パスワードマネージャーなどが機密として指定したコピーの扱い
+    #[serde(default)]
+    pub sensitive: SensitiveConfig,
+}
+fn default_field_separator_key() -> String {
+    "\t".to_owned()
+}
+fn default_split_width() -> usize {
+    80
+}
+fn default_grab_keyboard() -> bool {
+    true
 }
 impl Default for Config {
     fn default() -> Self {
@@ -117,6 +232,107 @@ impl Default for Config {
             max_line_length: 256,
             text_modifiers_hot_reload: false,
             text_modifiers: None,
+            capture_selection: CaptureSelection::default(),
+            keyboard_layout: KeyboardLayout::default(),
+            grab_keyboard: default_grab_keyboard(),
+            queue_journal: None,
+            restore_queue: RestoreQueue::default(),
+            registers: Vec::new(),
+            queue_order: QueueOrder::default(),
+            split_rule: SplitRule::default(),
+            split_regex: String::new(),
+            split_width: default_split_width(),
+            record_format: RecordFormat::default(),
+            field_separator_key: default_field_separator_key(),
+            select_columns: Vec::new(),
+            transpose: false,
+            line_ending: LineEnding::default(),
+            whitespace: Whitespace::default(),
+            copy_filter: CopyFilter::default(),
+            queue_limit: QueueLimit::default(),
+            import_path: None,
+            snapshot_path: None,
+            sensitive: SensitiveConfig::default(),
+        }
+    }
+}
+impl Config {
+    // 読み込んだ設定を検証して正規表現をコンパイルし、不正な場合は設定名を含めた理由を返す
+    pub fn check(&mut self) -> Result<(), String> {
+        if self.select_columns.contains(&0) {
+            return Err("select_columns の列番号は1以上で指定してください".to_owned());
+        }
+        build_splitter(
+            self.split_rule,
+            &self.split_regex,
+            self.split_width,
+            self.line_ending == LineEnding::Keep,
+        )?;
+        self.copy_filter.compile()
+    }
+    // デフォルトレジスタを先頭にしたレジスタ名の一覧
+    pub fn register_names(&self) -> Vec<String> {
+        let mut names = vec![DEFAULT_REGISTER.to_owned()];
+        for name in &self.registers {
+            if !name.is_empty() && !names.contains(name) {
+                names.push(name.clone());
+            }
+        }
+        names
+    }
+}
+
+// X11ではCtrl+CでコピーされるCLIPBOARDと、選択しただけでコピーされるPRIMARYの2つのセレクションがある。
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum CaptureSelection {
+    #[default]
+    Clipboard,
+    Primary,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum KeyboardLayout {
+    #[default]
+    Us,
+    Jp,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum RestoreQueue {
+    // 起動時に問い合わせる
+    #[default]
+    Ask,
+    Always,
+    Never,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum QueueOrder {
+    // 古い行から順にペーストする
+    #[default]
+    Fifo,
+    // 新しい行から順にペーストする
+    Lifo,
+    // コピーした単位は古い順に、コピー単位の中では最後の行から順にペーストする
+    GroupReversed,
+}
+impl QueueOrder {
+    pub fn next(self) -> Self {
+        match self {
+            QueueOrder::Fifo => QueueOrder::Lifo,
+            QueueOrder::Lifo => QueueOrder::GroupReversed,
+            QueueOrder::GroupReversed => QueueOrder::Fifo,
+        }
+    }
+    pub fn name(self) -> &'static str {
+        match self {
+            QueueOrder::Fifo => "古い順",
+            QueueOrder::Lifo => "新しい順",
+            QueueOrder::GroupReversed => "コピー単位で逆順",
         }
     }
 }
@@ -142,6 +358,26 @@ pub struct RunMode {
     max_line_len: usize,
     hook_mode: HookMode,
     palette_no: usize,
+    registers: Vec<String>,
+    register_no: usize,
+    sticky_mode: bool,
+    queue_order: QueueOrder,
+    split_rule: SplitRule,
+    split_regex: String,
+    split_width: usize,
+    // ホットキーで指定した、次のコピーだけに使う分割方法
+    next_split_rule: Option<SplitRule>,
+    record_format: RecordFormat,
+    field_separator_keyseq: String,
+    select_columns: Vec<usize>,
+    transpose: bool,
+    line_ending: LineEnding,
+    whitespace: Whitespace,
+    copy_filter: CopyFilter,
+    queue_limit: QueueLimit,
+    import_path: Option<String>,
+    snapshot_path: Option<String>,
+    sensitive: SensitiveConfig,
 }
 impl Default for RunMode {
     fn default() -> Self {
@@ -155,6 +391,25 @@ impl Default for RunMode {
             max_line_len: 512,
             hook_mode: HookMode::Override,
             palette_no: 0,
+            registers: vec![DEFAULT_REGISTER.to_owned()],
+            register_no: 0,
+            sticky_mode: false,
+            queue_order: QueueOrder::Fifo,
+            split_rule: SplitRule::Line,
+            split_regex: String::new(),
+            split_width: 80,
+            next_split_rule: None,
+            record_format: RecordFormat::Off,
+            field_separator_keyseq: String::new(),
+            select_columns: Vec::new(),
+            transpose: false,
+            line_ending: LineEnding::Strip,
+            whitespace: Whitespace::Keep,
+            copy_filter: CopyFilter::default(),
+            queue_limit: QueueLimit::default(),
+            import_path: None,
+            snapshot_path: None,
+            sensitive: SensitiveConfig::default(),
         }
     }
 }
@@ -163,11 +418,34 @@ impl RunMode {
         RunMode::default()
     }
     pub fn set_config(&mut self, config: Config) {
+        // リロードでレジスタが減った場合でも、選択中のレジスタが残っていればそのまま使う
+        let current = self.get_register_name();
+        self.registers = config.register_names();
+        self.register_no = self
+            .registers
+            .iter()
+            .position(|name| *name == current)
+            .unwrap_or(0);
         self.tabindex_keyseq = config.tabindex_key;
         self.line_delay_msec = config.line_delay_msec;
         self.char_delay_msec = config.char_delay_msec;
         self.max_line_len = config.max_line_length;
         self.paste_timeout = config.paste_timeout;
+        self.queue_order = config.queue_order;
+        self.split_rule = config.split_rule;
+        self.split_regex = config.split_regex;
+        self.split_width = config.split_width;
+        self.record_format = config.record_format;
+        self.field_separator_keyseq = config.field_separator_key;
+        self.select_columns = config.select_columns;
+        self.transpose = config.transpose;
+        self.line_ending = config.line_ending;
+        self.whitespace = config.whitespace;
+        self.copy_filter = config.copy_filter;
+        self.queue_limit = config.queue_limit;
+        self.import_path = config.import_path;
+        self.snapshot_path = config.snapshot_path;
+        self.sensitive = config.sensitive;
     }
     pub fn set_burst_mode(&mut self, burst_mode: bool) {
         self.burst_mode = burst_mode
@@ -208,5 +486,115 @@ impl RunMode {
     pub fn set_palette_no(&mut self, no: usize) {
         self.palette_no = no;
     }
+    pub fn get_register_count(&self) -> usize {
+        self.registers.len()
+    }
+    pub fn get_register_no(&self) -> usize {
+        self.register_no
+    }
+    pub fn set_register_no(&mut self, no: usize) {
+        self.register_no = no;
+    }
+    pub fn get_register_name(&self) -> String {
+        self.registers[self.register_no].clone()
+    }
+    // ペーストしても行をキューから取り除かないモード
+    pub fn set_sticky_mode(&mut self, sticky_mode: bool) {
+        self.sticky_mode = sticky_mode;
+    }
+    pub fn is_sticky_mode(&self) -> bool {
+        self.sticky_mode
+    }
+    pub fn set_queue_order(&mut self, order: QueueOrder) {
+        self.queue_order = order;
+    }
+    pub fn get_queue_order(&self) -> QueueOrder {
+        self.queue_order
+    }
+    // 次のコピーに使う分割方法
+    pub fn get_split_rule(&self) -> SplitRule {
+        self.next_split_rule.unwrap_or(self.split_rule)
+    }
+    pub fn set_next_split_rule(&mut self, rule: Option<SplitRule>) {
+        self.next_split_rule = rule;
+    }
+    pub fn get_split_regex(&self) -> String {
+        self.split_regex.clone()
+    }
+    pub fn get_split_width(&self) -> usize {
+        self.split_width
+    }
+    pub fn has_next_split_rule(&self) -> bool {
+        self.next_split_rule.is_some()
+    }
+    pub fn get_record_format(&self) -> RecordFormat {
+        self.record_format
+    }
+    pub fn get_field_separator_keyseq(&self) -> String {
+        self.field_separator_keyseq.clone()
+    }
+    pub fn get_select_columns(&self) -> Vec<usize> {
+        self.select_columns.clone()
+    }
+    pub fn is_transpose(&self) -> bool {
+        self.transpose
+    }
+    pub fn get_line_ending(&self) -> LineEnding {
+        self.line_ending
+    }
+    pub fn get_whitespace(&self) -> Whitespace {
+        self.whitespace
+    }
+    pub fn get_copy_filter(&self) -> CopyFilter {
+        self.copy_filter.clone()
+    }
+    pub fn get_queue_limit(&self) -> QueueLimit {
+        self.queue_limit.clone()
+    }
+    pub fn get_import_path(&self) -> Option<String> {
+        self.import_path.clone()
+    }
+    pub fn get_snapshot_path(&self) -> Option<String> {
+        self.snapshot_path.clone()
+    }
+    pub fn get_sensitive(&self) -> SensitiveConfig {
+        self.sensitive.clone()
+    }
 }
 ////
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn column_zero_is_rejected_with_the_setting_name() {
+        let mut config = Config {
+            select_columns: vec![2, 0],
+            ..Config::default()
+        };
+        let e = config.check().unwrap_err();
+        assert!(e.contains("select_columns"), "{e}");
+        assert!(Config::default().check().is_ok());
+    }
+
+    #[test]
+    fn keeping_line_endings_requires_a_line_based_rule() {
+        let mut config = Config {
+            line_ending: LineEnding::Keep,
+            split_rule: SplitRule::Paragraph,
+            ..Config::default()
+        };
+        assert!(config.check().unwrap_err().contains("line_ending"));
+        for split_rule in [SplitRule::Line, SplitRule::None] {
+            assert!(Config { split_rule, ..config.clone() }.check().is_ok());
+        }
+    }
+
+    #[test]
+    fn invalid_exclude_pattern_is_rejected_at_load() {
+        let mut config = Config::default();
+        config.copy_filter.exclude_patterns = vec!["[".to_owned()];
+        assert!(config.check().unwrap_err().contains("exclude_patterns"));
+    }
+}
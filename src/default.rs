@@ -1,21 +1,28 @@
+use crate::clipboard::ClipboardLock;
 use crate::config::*;
+use crate::filter::{Dedupe, FilterReport};
+use crate::hook;
+use crate::importer;
+use crate::injector;
+use crate::inspector;
+use crate::journal::{self, Journal};
+use crate::keycode::*;
+use crate::queue::{register_journal_path, ClipboardData, LimitReport, Registers};
+use crate::secret::{self, OnSensitive};
+use crate::snapshot;
+use crate::splitter::{
+    build_splitter, finish_entry, parse_record, split_line_ending, transform_table, LineEnding,
+    LineSplitter, RecordSplitter, Splitter, TerminatedLineSplitter,
+};
+use crate::window;
 use multiline_parser_pluginlib::{plugin::*, result::*};
 use once_cell::unsync::*;
-use send_input::keyboard::windows::*;
-use std::ffi::{CString, OsString};
-use std::os::windows::ffi::{OsStrExt, OsStringExt};
+use std::collections::HashSet;
 use std::sync::{Arc, Condvar};
-use std::time::{Duration, Instant};
-use std::{
-    collections::VecDeque,
-    sync::{Mutex, RwLock},
-};
+use std::sync::{Mutex, RwLock};
+use std::time::Instant;
+use zeroize::{Zeroize, Zeroizing};
 use toolbox::config_loader::ConfigLoader;
-use windows::Win32::{
-    Foundation::*,
-    System::{DataExchange::*, Memory::*, SystemServices::*, WindowsProgramming::*},
-    UI::{Input::KeyboardAndMouse::*, WindowsAndMessaging::*},
-};
 
 // ALTキーが押されているかどうかのステート
 #[derive(Debug, PartialEq)]
@@ -24,82 +31,17 @@ enum EhKeyState {
     Alt,
 }
 
-struct ClipboardData {
-    data: VecDeque<String>,
-    copied_lines: Vec<usize>,
-    add_line_count: usize,
-}
-impl ClipboardData {
-    pub fn new() -> Self {
-        ClipboardData {
-            data: VecDeque::new(),
-            copied_lines: Vec::new(),
-            add_line_count: 0,
-        }
-    }
-    pub fn pop_back(&mut self) -> Option<String> {
-        self.data.pop_back()
-    }
-    pub fn commit_copy_lines(&mut self) {
-        self.copied_lines.push(self.add_line_count);
-        self.add_line_count = 0;
-    }
-    pub fn add_clipboard(&mut self, data: String) {
-        self.data.push_front(data);
-        self.add_line_count += 1;
-    }
-    pub fn clipboard_clear(&mut self) {
-        self.data.clear()
-    }
-    pub fn get_clipboard_lines(&self) -> usize {
-        self.data.len()
-    }
-    pub fn undo_data(&mut self) -> usize {
-        let lines = self.copied_lines.len();
-        if lines == 0 {
-            return 0;
-        }
-        self.remove_data(self.copied_lines[lines - 1])
-    }
-    pub fn remove_data(&mut self, delete_count: usize) -> usize {
-        let data_total = self.data.len();
-
-        if data_total == 0 {
-            return 0;
-        }
-        let mut actual_total_deletes = 0;
-        for i in 0..delete_count {
-            if i < data_total {
-                self.data.pop_front();
-                actual_total_deletes += 1;
-            } else {
-                break;
-            }
-        }
-        let e = self.copied_lines.len();
-        let mut total_deletes = actual_total_deletes;
-        for _i in 0..e {
-            let lines = self.copied_lines.pop().unwrap();
-            if lines <= total_deletes {
-                total_deletes -= lines
-            } else {
-                self.copied_lines.push(lines - total_deletes);
-            };
-        }
-        actual_total_deletes
-    }
-}
-
-static mut CLIPBOARD: Lazy<Mutex<ClipboardData>> = Lazy::new(|| Mutex::new(ClipboardData::new()));
+static mut CLIPBOARD: Lazy<Mutex<Registers>> = Lazy::new(|| Mutex::new(Registers::new()));
 static mut THREAD_MUTEX: Lazy<Mutex<u32>> = Lazy::new(|| Mutex::new(0));
-static mut KEY_MAP: Lazy<RwLock<Vec<bool>>> = Lazy::new(|| RwLock::new(vec![false; 256]));
+const KEY_MAP_SIZE: usize = 256;
+static mut KEY_MAP: Lazy<RwLock<Vec<bool>>> = Lazy::new(|| RwLock::new(vec![false; KEY_MAP_SIZE]));
 static mut RUN_MODE: Lazy<RwLock<RunMode>> = Lazy::new(|| RwLock::new(RunMode::default()));
 static mut TXT_MODIFIER: Lazy<RwLock<PluginManager>> = Lazy::new(|| {
     let conf: MasterConfig = ConfigLoader::load_file("config.toml");
     RwLock::new(PluginManager::new(&conf.plugin_directory))
 });
 // CTRLコンボキーのハンドラ
-static mut EH_CTL: Lazy<RwLock<Vec<Box<dyn Fn(&Vec<bool>, EhKeyState) -> ComboKey>>>> =
+static mut EH_CTL: Lazy<RwLock<Vec<Box<dyn Fn(&Vec<bool>, u32, EhKeyState) -> ComboKey>>>> =
     Lazy::new(|| RwLock::new(Vec::new()));
 const MAX_MODIFIER_PALETTES: usize = 9;
 
@@ -114,6 +56,11 @@ pub fn get_mode() -> RunMode {
     unsafe{(*RUN_MODE.read().unwrap()).clone()}
 }
 static mut CB_IN_COPY: Lazy<RwLock<bool>> = Lazy::new(|| RwLock::new(false));
+// 最後に読み込んだクリップボードのシーケンス番号
+static mut LAST_COPY_SEQUENCE: Lazy<Mutex<Option<u32>>> = Lazy::new(|| Mutex::new(None));
+// 最後にコピーしたレジスタと内容（同じ内容を続けてコピーした場合に無視するため）
+// アンドゥや削除をした後は、同じ内容をもう一度コピーできるように消しておく。
+static mut LAST_COPY_TEXT: Lazy<Mutex<Option<(String, String)>>> = Lazy::new(|| Mutex::new(None));
 pub fn update_clipboard() {
     let mut in_copy = unsafe { CB_IN_COPY.write().unwrap() };
 
@@ -127,6 +74,65 @@ pub fn update_clipboard() {
         println!("その他操作によりclipboardが変更された");
     }
 }
+// 選択しただけでコピーとして扱う（X11のPRIMARYセレクション）
+pub fn copy_selection() {
+    async_std::task::spawn(copy_clipboard());
+}
+// レジスタごとのジャーナルを開き、前回のキューが残っていれば復元する。
+pub fn open_queue_journal(config: &Config) {
+    let path = match &config.queue_journal {
+        Some(path) => path,
+        None => return,
+    };
+    let mut journals = Vec::new();
+    for name in config.register_names() {
+        let journal_path = register_journal_path(path, &name);
+        match Journal::open(&journal_path) {
+            Ok((journal, records)) => {
+                let mut restored = ClipboardData::new();
+                restored.restore(records);
+                journals.push((name, journal, restored));
+            }
+            Err(e) => {
+                println!("❌  ジャーナル {journal_path} を開けませんでした。キューは保存されません。({e})");
+                return;
+            }
+        }
+    }
+    let mut registers = unsafe { CLIPBOARD.lock().unwrap() };
+    registers.set_journal_path(path, config.restore_queue);
+    let lines: usize = journals
+        .iter()
+        .map(|(_, _, restored)| restored.get_clipboard_lines())
+        .sum();
+    let restore = lines > 0 && journal::confirm_restore(config.restore_queue, lines);
+    for (name, journal, restored) in journals {
+        let mut queue = if restore {
+            println!(
+                "♻  レジスタ「{name}」に前回のキューを {} 行復元しました。",
+                restored.get_clipboard_lines()
+            );
+            restored
+        } else {
+            ClipboardData::new()
+        };
+        queue.attach_journal(journal);
+        // 機密の行の内容はプロセスの終了とともに破棄されているので、トークンだけが残っている
+        let discarded = queue.remove_matching(secret::is_token);
+        if discarded > 0 {
+            println!("🔐  レジスタ「{name}」の機密の行 {discarded} 行は復元できないため破棄しました。");
+        }
+        registers.insert(&name, queue);
+    }
+}
+// 選択中のレジスタのキューを操作する
+pub fn with_active_queue<R>(f: impl FnOnce(&mut ClipboardData) -> R) -> R {
+    with_queue(&get_mode().get_register_name(), f)
+}
+pub fn with_queue<R>(register: &str, f: impl FnOnce(&mut ClipboardData) -> R) -> R {
+    let mut registers = unsafe { CLIPBOARD.lock().unwrap() };
+    f(registers.get(register))
+}
 pub fn load_encoder(encoder_list: Vec<String>) {
     let mut pm = unsafe { TXT_MODIFIER.write().unwrap() };
     if encoder_list.len() == 0 {
@@ -150,65 +156,39 @@ pub fn load_encoder(encoder_list: Vec<String>) {
     show_current_mod_palette(&mut pm, palette_no);
 }
 
-static mut DLL: Lazy<Mutex<libloading::Library>> = Lazy::new(|| {
-    Mutex::new(unsafe {
-        match libloading::Library::new("inputctl.dll") {
-            Err(_e) => {
-                println!("🔴  必須ライブラリ inputctl.dll が読み込めませんでした。");
-                std::process::exit(-1);
-            }
-            Ok(lib) => lib,
-        }
-    })
-});
-
-type DllCtrlNoticeApi = unsafe extern "C" fn() -> bool;
-type DllSetHookApi = unsafe extern "C" fn() -> bool;
-fn enable_ctrl_v() {
-    let dll = unsafe { DLL.lock().unwrap() };
-    // 有効化する
-    unsafe {
-        let notice_ctrl_v: libloading::Symbol<DllCtrlNoticeApi> =
-            dll.get(b"notice_ctrl_v").unwrap();
-        notice_ctrl_v();
-    }
-}
-fn disable_ctrl_v() {
-    let dll = unsafe { DLL.lock().unwrap() };
-    // 無効化する
-    unsafe {
-        let ignore_ctrl_v: libloading::Symbol<DllCtrlNoticeApi> =
-            dll.get(b"ignore_ctrl_v").unwrap();
-        ignore_ctrl_v();
-    }
+pub fn key_down(_keystate: u32, event: &KeyEvent) -> PluginResult {
+    handle_key_down(event).0
 }
 
-pub fn key_down(_keystate: u32, stroke_msg: KBDLLHOOKSTRUCT) -> PluginResult {
-    if stroke_msg.flags.0 & (LLKHF_INJECTED.0 | LLKHF_LOWER_IL_INJECTED.0) == 0
-        || stroke_msg.dwExtraInfo == 0
-    {
-        // println!("[key down] stroke={stroke_msg:?}");
+// key_downと同じ処理を行い、Ctrl+Vでペーストを開始した場合は合わせてtrueを返す。
+// evdevのようにキーを自分で再送するバックエンドは、これを見てVキーをアプリケーションに渡すかどうかを決める。
+pub fn handle_key_down(event: &KeyEvent) -> (PluginResult, bool) {
+    let vk = event.vk as usize;
+    if !event.injected && vk < KEY_MAP_SIZE {
+        // println!("[key down] event={event:?}");
         let is_burst = unsafe {
             let mut lmap = KEY_MAP.write().unwrap();
-            lmap[stroke_msg.vkCode as usize] = true;
+            lmap[vk] = true;
             let mode = RUN_MODE.read().unwrap();
             mode.is_burst_mode()
         };
-        if judge_combo_key(stroke_msg.vkCode as usize) != ComboKey::None && is_burst {
-            return PluginResult::NoChain;
+        let combo = judge_combo_key(vk, event.modifiers);
+        let paste_started = combo == ComboKey::Paste;
+        if combo != ComboKey::None && is_burst {
+            return (PluginResult::NoChain, paste_started);
         }
+        return (PluginResult::Success, paste_started);
     }
-    PluginResult::Success
+    (PluginResult::Success, false)
 }
 
-pub fn key_up(_keystate: u32, stroke_msg: KBDLLHOOKSTRUCT) -> PluginResult {
-    if stroke_msg.flags.0 & (LLKHF_INJECTED.0 | LLKHF_LOWER_IL_INJECTED.0) == 0
-        || stroke_msg.dwExtraInfo == 0
-    {
-        // println!("[key up] stroke={stroke_msg:?}");
+pub fn key_up(_keystate: u32, event: &KeyEvent) -> PluginResult {
+    let vk = event.vk as usize;
+    if !event.injected && vk < KEY_MAP_SIZE {
+        // println!("[key up] event={event:?}");
         unsafe {
             let mut lmap = KEY_MAP.write().unwrap();
-            lmap[stroke_msg.vkCode as usize] = false;
+            lmap[vk] = false;
         }
     }
     PluginResult::Success
@@ -217,8 +197,11 @@ pub fn key_up(_keystate: u32, stroke_msg: KBDLLHOOKSTRUCT) -> PluginResult {
 async fn undo_clipboard() {
     print!("⏪  ");
     show_operation_message("クリップボードに対するアンドゥ");
-    let mut cb_data = unsafe { CLIPBOARD.lock().unwrap() };
+    let register = get_mode().get_register_name();
+    let mut registers = unsafe { CLIPBOARD.lock().unwrap() };
+    let cb_data = registers.get(&register);
     let actual_delete_lines = cb_data.undo_data();
+    unsafe { *LAST_COPY_TEXT.lock().unwrap() = None };
     println!(
         "削除した行数 {}行 残り {}行",
         actual_delete_lines,
@@ -226,45 +209,137 @@ async fn undo_clipboard() {
     );
 }
 
+async fn redo_clipboard() {
+    print!("⏩  ");
+    show_operation_message("クリップボードに対するやり直し");
+    let register = get_mode().get_register_name();
+    let mut registers = unsafe { CLIPBOARD.lock().unwrap() };
+    let cb_data = registers.get(&register);
+    match cb_data.redo_data() {
+        Some((lines, groups)) => println!(
+            "コピー {}回分 {}行を復元しました 残り {}行",
+            groups,
+            lines,
+            cb_data.get_clipboard_lines()
+        ),
+        None => println!("やり直せる操作がありません。"),
+    }
+}
+
 async fn copy_clipboard() {
     print!("💾  ");
     show_operation_message("コピー");
-    let mut cb = unsafe { CLIPBOARD.lock().unwrap() };
-    let iclip = Clipboard::open();
-    unsafe {
-        load_data_from_clipboard(&mut cb);
+    // ペースト処理と同じ順番（クリップボード→キュー）でロックすること
+    let register = get_mode().get_register_name();
+    let mut iclip = ClipboardLock::open();
+    let mut registers = unsafe { CLIPBOARD.lock().unwrap() };
+    let cb = registers.get(&register);
+    // 同じ変更に対して複数回通知された場合は読み込まない
+    let seq = iclip.sequence_number();
+    let mut last_seq = unsafe { LAST_COPY_SEQUENCE.lock().unwrap() };
+    if *last_seq == Some(seq) {
+        return;
     }
+    *last_seq = Some(seq);
+    load_data_from_clipboard(cb, &mut iclip);
 }
 
-async fn reset_clipboard() {
-    print!("🧺  ");
-    show_operation_message("クリップボードデータの削除");
-    let mut cb = unsafe { CLIPBOARD.lock().unwrap() };
-    cb.clipboard_clear();
-}
-pub struct Clipboard {}
-impl Clipboard {
-    fn open() -> Self {
-        unsafe {
-            OpenClipboard(HWND::default());
+async fn import_configured_file() {
+    let mode = get_mode();
+    match mode.get_import_path() {
+        Some(path) => {
+            if let Err(e) = importer::import_file(&path, &mode) {
+                println!("❌  {path} を取り込めませんでした。({e})");
+            }
         }
-        Clipboard {}
+        None => println!("取り込むファイル（import_path）が設定されていません。"),
     }
 }
-impl Drop for Clipboard {
-    fn drop(&mut self) {
-        unsafe {
-            CloseClipboard();
+
+async fn snapshot_configured_file(load: bool) {
+    let mode = get_mode();
+    let path = match mode.get_snapshot_path() {
+        Some(path) => path,
+        None => {
+            println!("スナップショットのファイル（snapshot_path）が設定されていません。");
+            return;
         }
+    };
+    let register = mode.get_register_name();
+    let result = if load {
+        snapshot::load_register(&path, &register)
+    } else {
+        snapshot::export_register(&path, &register)
+    };
+    if let Err(e) = result {
+        println!("❌  スナップショット {path} を処理できませんでした。({e})");
+    }
+}
+
+// 機密の行を保持している間、期限切れや参照されなくなった内容を破棄するスレッド
+// キュー（CLIPBOARD）をロックしてから確認するので、コピー中に保持した内容を破棄することはない。
+static mut SECRET_PURGER_RUNNING: Lazy<Mutex<bool>> = Lazy::new(|| Mutex::new(false));
+fn spawn_secret_purger() {
+    let mut running = unsafe { SECRET_PURGER_RUNNING.lock().unwrap() };
+    if *running {
+        return;
     }
+    *running = true;
+    std::thread::spawn(|| loop {
+        std::thread::sleep(std::time::Duration::from_secs(1));
+        let mut registers = unsafe { CLIPBOARD.lock().unwrap() };
+        let expired = {
+            let last_pasted = unsafe { LAST_PASTED.lock().unwrap() };
+            secret::purge(|token| {
+                last_pasted.as_deref() == Some(token)
+                    || registers.contains(token)
+            })
+        };
+        if !expired.is_empty() {
+            let removed: usize = registers
+                .iter_mut()
+                .map(|queue| queue.remove_matching(|line| expired.iter().any(|token| token == line)))
+                .sum();
+            println!("🔐  期限切れの機密の行を {removed} 行破棄しました。");
+        }
+        if secret::is_empty() {
+            let mut running = unsafe { SECRET_PURGER_RUNNING.lock().unwrap() };
+            *running = false;
+            return;
+        }
+    });
 }
 
+async fn reset_clipboard() {
+    print!("🧺  ");
+    show_operation_message("クリップボードデータの削除");
+    let register = get_mode().get_register_name();
+    let mut registers = unsafe { CLIPBOARD.lock().unwrap() };
+    registers.get(&register).clipboard_clear();
+    unsafe { *LAST_COPY_TEXT.lock().unwrap() = None };
+}
 fn show_operation_message<T: Into<String>>(operation: T) {
-    let active_window = unsafe { GetForegroundWindow() };
-    if active_window.0 != 0 {
+    // レジスタを使用している場合は、どのレジスタに対する操作かを表示する
+    let register = {
+        let mode = get_mode();
+        if mode.get_register_count() > 1 {
+            format!("レジスタ「{}」に対する", mode.get_register_name())
+        } else {
+            String::new()
+        }
+    };
+    if let Some(window) = window::active_window() {
+        println!(
+            "ウィンドウ「{}」({})上で{}{}操作が行われました。",
+            window.title,
+            window.process_name,
+            register,
+            operation.into()
+        );
+    } else if !register.is_empty() {
         println!(
-            "ウィンドウ「{}」上で{}操作が行われました。",
-            get_window_text(active_window),
+            "{}{}操作が行われました。（アクティブウィンドウに対するフォーカスが失われています）",
+            register,
             operation.into()
         );
     } else {
@@ -276,6 +351,8 @@ fn show_operation_message<T: Into<String>>(operation: T) {
 enum ComboKey {
     None,
     Combo(u64),
+    // Ctrl+Vでキューからのペーストを開始した
+    Paste,
 }
 fn show_current_mod_palette(pm: &mut PluginManager, palette_no: usize) {
     let plugin_list = pm.get_plugin_ordered_list().clone();
@@ -301,17 +378,12 @@ fn show_current_mod_palette(pm: &mut PluginManager, palette_no: usize) {
 
 // キーイベントハンドラの初期化を行う。初期化時に呼び出される。
 pub fn eh_init() {
-    let dll = unsafe { DLL.lock().unwrap() };
-    unsafe {
-        let sethook: libloading::Symbol<DllSetHookApi> = dll.get(b"sethook").unwrap();
-        sethook();
-    }
     let mut eh_table = unsafe { EH_CTL.write().unwrap() };
-    for _ in 0..255 {
-        eh_table.push(Box::new(move |_, _| ComboKey::None));
+    for _ in 0..KEY_MAP_SIZE {
+        eh_table.push(Box::new(move |_, _, _| ComboKey::None));
     }
     // CTRL+C と CTRL+ALT+Cが押された時の定義
-    eh_table['C' as usize] = Box::new(move |_, ks| {
+    eh_table['C' as usize] = Box::new(move |_, _, ks| {
         if ks == EhKeyState::None {
             let mut in_copy = unsafe { CB_IN_COPY.write().unwrap() };
             *in_copy = true;
@@ -321,7 +393,7 @@ pub fn eh_init() {
             ComboKey::Combo(3)
         }
     });
-    eh_table['X' as usize] = Box::new(move |_, ks| {
+    eh_table['X' as usize] = Box::new(move |_, _, ks| {
         if ks == EhKeyState::None {
             let mut in_copy = unsafe { CB_IN_COPY.write().unwrap() };
             *in_copy = true;
@@ -332,24 +404,104 @@ pub fn eh_init() {
         }
     });
     // CTRL+Vが押された時の定義
-    eh_table['V' as usize] = Box::new(move |_, ks| {
+    eh_table['V' as usize] = Box::new(move |_, _, ks| {
         // 基本的に重たい操作なので非同期で行う
         // 意訳：さっさとフックプロシージャから復帰しないとキーボードがハングする。
         // ただし、Clipboardをロックしてから戻らないとだめ。
         if ks == EhKeyState::None {
+            // 縮退モードではCtrl+Vを抑止できないので、キーを握りつぶせるバーストモード以外はOSに任せる。
+            let is_burst = unsafe { RUN_MODE.read().unwrap().is_burst_mode() };
+            if !is_burst && !hook::can_suppress_ctrl_v() {
+                return ComboKey::None;
+            }
             // CTRL+Vの無効化
-            disable_ctrl_v();
-            let cb_lock_wait = Arc::new((Mutex::new(false), Condvar::new()));
-            async_std::task::spawn(paste(cb_lock_wait.clone()));
-            let (lock, _cond) = &*cb_lock_wait;
-            let _lock = lock.lock().unwrap(); // クリップボードがロックされるまで待つ。
+            hook::disable_ctrl_v();
+            spawn_paste(PasteSource::Queue);
+            ComboKey::Paste
+        } else {
+            // CTRL+ALT+V で最後にペーストした行をもう一度ペーストする
+            spawn_paste(PasteSource::LastPasted);
             ComboKey::Combo(1)
+        }
+    });
+    // CTRL+ALT+S でスティッキーモード（ペーストしても行を消費しない）を切り替える
+    eh_table['S' as usize] = Box::new(move |_, _, ks| {
+        if ks == EhKeyState::None {
+            ComboKey::None
         } else {
+            let mut mode = unsafe { RUN_MODE.write().unwrap() };
+            let sticky_mode = !mode.is_sticky_mode();
+            mode.set_sticky_mode(sticky_mode);
+            println!(
+                "{}",
+                [
+                    "📤  ペーストした行をキューから取り除くモードに切り替えました。",
+                    "📌  スティッキーモード（カーソル位置の行を取り除かずにペースト）に切り替えました。"
+                ][sticky_mode as usize]
+            );
+            ComboKey::Combo(4)
+        }
+    });
+    // CTRL+ALT+L で次のコピーだけ分割方法を切り替える（押すたびに次の方法へ）
+    eh_table['L' as usize] = Box::new(move |_, _, ks| {
+        if ks == EhKeyState::None {
             ComboKey::None
+        } else {
+            let mut mode = unsafe { RUN_MODE.write().unwrap() };
+            let mut rule = mode.get_split_rule().next();
+            // 区切りが設定されていない、line_ending = "keep" と併用できないなど、今の設定で使えない方法は飛ばす
+            let keep_line_endings = mode.get_line_ending() == LineEnding::Keep;
+            let (pattern, width) = (mode.get_split_regex(), mode.get_split_width());
+            while build_splitter(rule, &pattern, width, keep_line_endings).is_err() {
+                rule = rule.next();
+            }
+            mode.set_next_split_rule(Some(rule));
+            println!("✂  次のコピーの分割方法を「{}」にしました。", rule.name());
+            ComboKey::Combo(4)
+        }
+    });
+    // CTRL+ALT+F で import_path のファイルを取り込む
+    eh_table['F' as usize] = Box::new(move |_, _, ks| {
+        if ks == EhKeyState::None {
+            ComboKey::None
+        } else {
+            async_std::task::spawn(import_configured_file());
+            ComboKey::Combo(4)
+        }
+    });
+    // CTRL+ALT+E で snapshot_path にキューを書き出す（SHIFTを押している場合は復元する）
+    eh_table['E' as usize] = Box::new(move |lmap, modifiers, ks| {
+        if ks == EhKeyState::None {
+            ComboKey::None
+        } else {
+            let load = is_shift_pressed(lmap, modifiers);
+            async_std::task::spawn(snapshot_configured_file(load));
+            ComboKey::Combo(4)
+        }
+    });
+    // CTRL+ALT+O でペーストする行の順番を切り替える
+    eh_table['O' as usize] = Box::new(move |_, _, ks| {
+        if ks == EhKeyState::None {
+            ComboKey::None
+        } else {
+            let mut mode = unsafe { RUN_MODE.write().unwrap() };
+            let order = mode.get_queue_order().next();
+            mode.set_queue_order(order);
+            println!("🔀  ペーストする順番を「{}」に切り替えました。", order.name());
+            ComboKey::Combo(4)
+        }
+    });
+    // CTRL+ALT+N でスティッキーモードのカーソルを次の行へ（SHIFTを押している場合は前の行へ）移動する
+    eh_table['N' as usize] = Box::new(move |lmap, modifiers, ks| {
+        if ks == EhKeyState::None {
+            ComboKey::None
+        } else {
+            async_std::task::spawn(move_cursor(!is_shift_pressed(lmap, modifiers)));
+            ComboKey::Combo(4)
         }
     });
     // 0が押されたときの定義
-    eh_table['0' as usize] = Box::new(move |_, ks| {
+    eh_table['0' as usize] = Box::new(move |_, _, ks| {
         if ks == EhKeyState::None {
             ComboKey::None
         } else {
@@ -370,7 +522,7 @@ pub fn eh_init() {
         }
     });
 
-    eh_table['Z' as usize] = Box::new(move |_, ks| {
+    eh_table['Z' as usize] = Box::new(move |_, _, ks| {
         if ks == EhKeyState::None {
             ComboKey::None
         } else {
@@ -379,7 +531,7 @@ pub fn eh_init() {
         }
     });
     for vkey in 0x31..=0x39 {
-        eh_table[vkey] = Box::new(move |_, ks| {
+        eh_table[vkey] = Box::new(move |_, _, ks| {
             if ks == EhKeyState::None {
                 ComboKey::None
             } else {
@@ -408,7 +560,7 @@ pub fn eh_init() {
             }
         });
     }
-    eh_table['Q' as usize] = Box::new(move |lmap, ks| {
+    eh_table['Q' as usize] = Box::new(move |lmap, modifiers, ks| {
         if ks == EhKeyState::None {
             // EhKeyState::None
             ComboKey::None
@@ -424,7 +576,7 @@ pub fn eh_init() {
             let mode = unsafe { &mut RUN_MODE.write().unwrap() };
             let palette_no = mode.get_palette_no();
             // パレット番号は0-max_palette_countまでを取る。
-            let palette_no = if lmap[VK_LSHIFT.0 as usize] {
+            let palette_no = if is_shift_pressed(lmap, modifiers) {
                 if usize::MIN == palette_no {
                     max_palette_count
                 } else {
@@ -440,14 +592,53 @@ pub fn eh_init() {
             ComboKey::Combo(4)
         }
     });
-    eh_table['M' as usize] = Box::new(move |lmap, ks| {
+    // CTRL+ALT+R でレジスタを切り替える（SHIFTを押している場合は逆順）
+    eh_table['R' as usize] = Box::new(move |lmap, modifiers, ks| {
+        if ks == EhKeyState::None {
+            ComboKey::None
+        } else {
+            let mut mode = unsafe { RUN_MODE.write().unwrap() };
+            let count = mode.get_register_count();
+            let register_no = mode.get_register_no();
+            let register_no = if is_shift_pressed(lmap, modifiers) {
+                (register_no + count - 1) % count
+            } else {
+                (register_no + 1) % count
+            };
+            mode.set_register_no(register_no);
+            println!(
+                "📚  レジスタ「{}」に切り替えました。",
+                mode.get_register_name()
+            );
+            ComboKey::Combo(4)
+        }
+    });
+    // CTRL+ALT+Y でアンドゥ・削除をやり直す
+    eh_table['Y' as usize] = Box::new(move |_, _, ks| {
+        if ks == EhKeyState::None {
+            ComboKey::None
+        } else {
+            async_std::task::spawn(redo_clipboard());
+            ComboKey::Combo(0)
+        }
+    });
+    // CTRL+ALT+I でキューのインスペクタを開く
+    eh_table['I' as usize] = Box::new(move |_, _, ks| {
+        if ks == EhKeyState::None {
+            ComboKey::None
+        } else {
+            inspector::spawn_inspector();
+            ComboKey::Combo(4)
+        }
+    });
+    eh_table['M' as usize] = Box::new(move |lmap, modifiers, ks| {
         if ks == EhKeyState::None {
             // EhKeyState::None
             ComboKey::None
         } else {
             // EhKeyState::Alt
             let mut mode = unsafe { RUN_MODE.write().unwrap() };
-            if lmap[VK_LSHIFT.0 as usize] {
+            if is_shift_pressed(lmap, modifiers) {
                 // CTRL+ALT+SHIFT+M
                 let im = mode.get_input_mode();
                 let (burst, im) = if im == InputMode::Clipboard {
@@ -485,34 +676,90 @@ pub fn eh_init() {
     });
 }
 
-fn judge_combo_key(vk: usize) -> ComboKey {
-    let lmap = unsafe { &mut KEY_MAP.read().unwrap() };
-    if lmap[VK_LCONTROL.0 as usize] == true {
+// ホストから通知された修飾キーの状態も合わせて判定する
+fn is_shift_pressed(lmap: &[bool], modifiers: u32) -> bool {
+    lmap[VK_LSHIFT] || modifiers & MOD_SHIFT != 0
+}
+
+fn judge_combo_key(vk: usize, modifiers: u32) -> ComboKey {
+    let lmap = unsafe { &KEY_MAP.read().unwrap() };
+    if lmap[VK_LCONTROL] || modifiers & MOD_CONTROL != 0 {
         let eh_table = unsafe { EH_CTL.read().unwrap() };
         let hook_mode = {
             let mode = unsafe { &mut RUN_MODE.write().unwrap() };
             mode.get_hook_mode()
         };
         // CTRL+ALTキー
-        if lmap[VK_LMENU.0 as usize] | lmap[VK_RMENU.0 as usize] {
+        if lmap[VK_LMENU] || lmap[VK_RMENU] || modifiers & MOD_ALT != 0 {
             // HookMode::OsStandard時は、CTRL+ALT+0以外を全て無効化する。
             if hook_mode == HookMode::OsStandard {
                 if vk == 0x30 {
-                    return eh_table[vk](lmap, EhKeyState::Alt);
+                    return eh_table[vk](lmap, modifiers, EhKeyState::Alt);
                 }
                 return ComboKey::None;
             }
-            return eh_table[vk](lmap, EhKeyState::Alt);
+            return eh_table[vk](lmap, modifiers, EhKeyState::Alt);
         }
         // HookMode::OsStandard時は、CTRL+ALT+0以外を全て無効化する。
         if hook_mode == HookMode::Override {
-            return eh_table[vk](lmap, EhKeyState::None);
+            return eh_table[vk](lmap, modifiers, EhKeyState::None);
         }
     }
     ComboKey::None
 }
 
-pub async fn paste(is_clipboard_locked: Arc<(Mutex<bool>, Condvar)>) {
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum PasteSource {
+    // キューの末尾（スティッキーモードの場合はカーソル位置）の行
+    Queue,
+    // 最後にペーストした行
+    LastPasted,
+}
+static mut LAST_PASTED: Lazy<Mutex<Option<String>>> = Lazy::new(|| Mutex::new(None));
+
+// 基本的に重たい操作なので非同期で行うが、クリップボードがロックされるまでは待つ。
+fn spawn_paste(source: PasteSource) {
+    let cb_lock_wait = Arc::new((Mutex::new(false), Condvar::new()));
+    async_std::task::spawn(paste(cb_lock_wait.clone(), source));
+    let (lock, _cond) = &*cb_lock_wait;
+    let _lock = lock.lock().unwrap(); // クリップボードがロックされるまで待つ。
+}
+
+async fn move_cursor(forward: bool) {
+    let register = get_mode().get_register_name();
+    let mut registers = unsafe { CLIPBOARD.lock().unwrap() };
+    let cb_data = registers.get(&register);
+    if cb_data.get_clipboard_lines() == 0 {
+        println!("クリップボードにデータがありません。");
+        return;
+    }
+    let cursor = cb_data.move_cursor(forward);
+    println!(
+        "👉  カーソルを {}/{} 行目に移動しました。{:?}",
+        cursor + 1,
+        cb_data.get_clipboard_lines(),
+        secret::mask(&cb_data.line_at_cursor().unwrap_or_default())
+    );
+}
+
+// 次にペーストする行を取り出す
+fn next_line(
+    cb: &mut ClipboardData,
+    source: PasteSource,
+    sticky_mode: bool,
+    order: QueueOrder,
+) -> Option<String> {
+    let mut last_pasted = unsafe { LAST_PASTED.lock().unwrap() };
+    let line = match source {
+        PasteSource::LastPasted => last_pasted.clone(),
+        PasteSource::Queue if sticky_mode => cb.line_at_cursor(),
+        PasteSource::Queue => cb.take(order),
+    }?;
+    *last_pasted = Some(line.clone());
+    Some(line)
+}
+
+pub async fn paste(is_clipboard_locked: Arc<(Mutex<bool>, Condvar)>, source: PasteSource) {
     let start = Instant::now();
     let mutex = unsafe { THREAD_MUTEX.lock().unwrap() };
     let input_mode = unsafe {
@@ -523,20 +770,25 @@ pub async fn paste(is_clipboard_locked: Arc<(Mutex<bool>, Condvar)>) {
         // ロックが完了した瞬間にnotify_oneをする必要がある。可能な限り早く実施する。
         // ロックするまでの間にsleepはもちろんのこと、MutexLock/RwLockなどの重たい処理を行ってはならない。
         let (lock, cond) = &*is_clipboard_locked;
-        let iclip = Clipboard::open();
+        let mut iclip = ClipboardLock::open();
         let mut is_lock = lock.lock().unwrap();
         *is_lock = true;
         cond.notify_one();
         // クリップボードを開く
-        let mut cb_data = CLIPBOARD.lock().unwrap();
-        EmptyClipboard();
-        if cb_data.get_clipboard_lines() == 0 {
-            println!("クリップボードにデータがありません。");
-            enable_ctrl_v();
-            return;
-        }
+        let register = RUN_MODE.read().unwrap().get_register_name();
+        let mut registers = CLIPBOARD.lock().unwrap();
+        let cb_data = registers.get(&register);
+        iclip.clear();
         // オプションをロードする
-        let (is_burst_mode, tabindex_keyseq, get_line_delay_msec, char_delay_msec, input_mode) = {
+        let (
+            is_burst_mode,
+            tabindex_keyseq,
+            get_line_delay_msec,
+            char_delay_msec,
+            input_mode,
+            sticky_mode,
+            queue_order,
+        ) = {
             let mode = RUN_MODE.read().unwrap();
             (
                 mode.is_burst_mode(),
@@ -544,36 +796,43 @@ pub async fn paste(is_clipboard_locked: Arc<(Mutex<bool>, Condvar)>) {
                 mode.get_line_delay_msec(),
                 mode.get_char_delay_msec(),
                 mode.get_input_mode(),
+                mode.is_sticky_mode(),
+                mode.get_queue_order(),
             )
         };
+        // ペーストする行数（スティッキーモードのバーストはカーソル位置から先頭まで）
+        let len = match source {
+            PasteSource::LastPasted => LAST_PASTED.lock().unwrap().is_some() as usize,
+            PasteSource::Queue if sticky_mode => cb_data.lines_from_cursor(),
+            PasteSource::Queue => cb_data.get_clipboard_lines(),
+        };
+        if len == 0 {
+            println!("クリップボードにデータがありません。");
+            hook::enable_ctrl_v();
+            return;
+        }
 
         if is_burst_mode && input_mode == InputMode::DirectKeyInput {
-            let mut kbd = Keyboard::new();
-            let len = cb_data.get_clipboard_lines();
-            kbd.new_delay(char_delay_msec);
-            kbd.append_input_chain(
-                KeycodeBuilder::default()
-                    .vk(VK_LCONTROL.0)
-                    .scan_code(virtual_key_to_scancode(VK_LCONTROL))
-                    .build(),
-            );
-            for key in tabindex_keyseq.chars() {
-                KeycodeBuilder::default()
-                    .char_build(key)
-                    .iter()
-                    .for_each(|keycode| kbd.append_input_chain(keycode.clone()));
-            }
-            for _i in 0..len {
-                if paste_impl(&mut cb_data) != InputMode::DirectKeyInput {
+            let mut kbd = KeySequence::new();
+            kbd.set_delay(char_delay_msec);
+            kbd.push_key(VK_LCONTROL, KeyAction::Press);
+            kbd.push_str(&tabindex_keyseq);
+            for i in 0..len {
+                let line = next_line(cb_data, source, sticky_mode, queue_order).unwrap();
+                if paste_entry(line, &mut iclip) != InputMode::DirectKeyInput {
                     println!("❎  バーストモードによるペースト操作が中断されました。");
                     break;
                 }
-                kbd.send_key();
+                if sticky_mode && i + 1 < len {
+                    cb_data.move_cursor(true);
+                }
+                injector::send_keys(&kbd);
                 // キーストロークとの間に数ミリ秒の待機時間を設ける
-                std::thread::sleep(Duration::from_millis(get_line_delay_msec))
+                injector::wait(get_line_delay_msec);
             }
         } else {
-            paste_impl(&mut cb_data);
+            let line = next_line(cb_data, source, sticky_mode, queue_order).unwrap();
+            paste_entry(line, &mut iclip);
         }
         // let wait = g_mode.read().unwrap().get_copy_wait_millis();
         // std::thread::sleep(Duration::from_millis(wait));
@@ -583,7 +842,7 @@ pub async fn paste(is_clipboard_locked: Arc<(Mutex<bool>, Condvar)>) {
         }
     };
     // std::thread::sleep(std::time::Duration::from_millis(1000));
-    enable_ctrl_v();
+    hook::enable_ctrl_v();
     // Clipboard以外ならキー入力は行わない。
     if input_mode == InputMode::DirectKeyInput {
         return;
@@ -596,93 +855,299 @@ pub async fn paste(is_clipboard_locked: Arc<(Mutex<bool>, Condvar)>) {
         elapsed
     );
     let wait = unsafe { RUN_MODE.read().unwrap().paste_timeout() };
-    if elapsed >= wait as u128 {
-        println!("💨  {wait} ms以上経過しているため、強制ペーストを実行します。");
+    // CTRL+ALT+VにはOSが反応しないので、常にCTRL+Vを送信する
+    if elapsed >= wait as u128 || source == PasteSource::LastPasted {
+        if source == PasteSource::Queue {
+            println!("💨  {wait} ms以上経過しているため、強制ペーストを実行します。");
+        }
         // 処理に300ms以上かかっていたら、キー入力は捨てられているので
         // クリップボードモードの場合はもう一度CTRL+Vストロークを送信して強制的にペーストさせる。
-        let mut kbd = Keyboard::new();
-        kbd.append_input_chain(
-            KeycodeBuilder::default()
-                .vk(VK_LCONTROL.0)
-                .scan_code(virtual_key_to_scancode(VK_LCONTROL))
-                .key_send_mode(KeySendMode::KeyDown)
-                .build(),
-        );
-        KeycodeBuilder::default()
-            .char_build('v')
-            .iter()
-            .for_each(|key_code| kbd.append_input_chain(key_code.clone()));
+        let mut kbd = KeySequence::new();
+        let released_alt = release_alt_key(&mut kbd);
+        kbd.push_key(VK_LCONTROL, KeyAction::Down);
+        kbd.push_str("v");
         let l_ctrl = unsafe {
             let lmap = KEY_MAP.read().unwrap();
-            lmap[VK_LCONTROL.0 as usize]
+            lmap[VK_LCONTROL]
         };
         if l_ctrl == false {
-            kbd.append_input_chain(
-                KeycodeBuilder::default()
-                    .vk(VK_LCONTROL.0)
-                    .scan_code(virtual_key_to_scancode(VK_LCONTROL))
-                    .key_send_mode(KeySendMode::KeyUp)
-                    .build(),
-            );
+            kbd.push_key(VK_LCONTROL, KeyAction::Up);
         }
-        kbd.send_key();
-    }
-}
-
-unsafe fn load_data_from_clipboard(cb_data: &mut ClipboardData) -> Option<()> {
-    let h_text = GetClipboardData(CF_UNICODETEXT.0);
-    match h_text {
-        Err(_) => None,
-        Ok(h_text) => {
-            // クリップボードにデータがあったらロックする
-            let p_text = GlobalLock(h_text.0);
-            // 今クリップボードにある内容をコピーする（改行で分割される）
-            // 後でここの挙動を変えても良さそう。
-            let text = u16_ptr_to_string(p_text as *const _).into_string().unwrap();
-            let current_len = cb_data.get_clipboard_lines();
-            for line in text.lines() {
-                let line_len = line.len();
-                if line_len != 0 {
-                    // cb.push_front(line.to_owned());
-                    cb_data.add_clipboard(line.to_owned());
-                } else {
-                    cb_data.add_clipboard("".to_owned());
-                    // cb.push_front("".to_owned());
-                }
-            }
-            cb_data.commit_copy_lines();
-            GlobalUnlock(h_text.0);
-            println!(
-                "クリップボードへ {} 行コピーしました",
-                cb_data.get_clipboard_lines() - current_len
-            );
-            Some(())
+        restore_alt_key(&mut kbd, released_alt);
+        injector::send_keys(&kbd);
+    }
+}
+
+// クリップボードが開かれている状態で呼び出すこと
+fn load_data_from_clipboard(cb_data: &mut ClipboardData, iclip: &mut ClipboardLock) -> Option<()> {
+    let text = Zeroizing::new(iclip.get_text()?);
+    let mode = get_mode();
+    let sensitive = iclip.is_sensitive() && mode.get_sensitive().on_sensitive != OnSensitive::Capture;
+    if sensitive && mode.get_sensitive().on_sensitive == OnSensitive::Skip {
+        println!("🔐  機密として指定された内容のため取り込みませんでした。");
+        return Some(());
+    }
+    {
+        // 機密の内容は比較のためにも残さない
+        let copied = match sensitive {
+            true => None,
+            false => Some((mode.get_register_name(), text.to_string())),
+        };
+        let mut last_copy = unsafe { LAST_COPY_TEXT.lock().unwrap() };
+        if mode.get_copy_filter().skip_repeated_copy && copied.is_some() && *last_copy == copied {
+            println!("♊  直前と同じ内容のコピーのため無視しました。");
+            return Some(());
+        }
+        *last_copy = copied;
+    }
+    // ホットキーで指定した分割方法は、このコピーだけに使う
+    unsafe { RUN_MODE.write().unwrap().set_next_split_rule(None) };
+    // 今クリップボードにある内容を、設定された分割方法でコピーする
+    let (lines, records) = split_text(&text, &mode);
+    match add_lines(cb_data, lines, records, sensitive, &mode) {
+        Ok((filter, limit)) => println!(
+            "{}クリップボードへ {} 行コピーしました{}",
+            if sensitive { "🔐  機密として" } else { "" },
+            limit.added,
+            filter_summary(&filter)
+        ),
+        Err((filter, e)) => {
+            println!("🟡  コピーした {} 行を取り込みませんでした。({e})", filter.kept);
+            // 取り込まなかった内容は、上限を変更した後などにもう一度コピーできるようにする
+            unsafe { *LAST_COPY_TEXT.lock().unwrap() = None };
+        }
+    }
+    Some(())
+}
+
+// テキストを設定された方法で分割する。レコードモードの場合はレコードの区切り文字も返す。
+pub fn split_text(text: &str, mode: &RunMode) -> (Vec<String>, Option<char>) {
+    let rule = mode.get_split_rule();
+    // ホットキーで分割方法を指定した場合はレコードモードより優先する
+    let records = match mode.has_next_split_rule() {
+        true => None,
+        false => mode.get_record_format().delimiter(),
+    };
+    if let Some(delimiter) = records {
+        return (RecordSplitter::new(delimiter).split(text), records);
+    }
+    let keep_line_endings = mode.get_line_ending() == LineEnding::Keep;
+    let splitter = match build_splitter(
+        rule,
+        &mode.get_split_regex(),
+        mode.get_split_width(),
+        keep_line_endings,
+    ) {
+        Ok(splitter) => splitter,
+        Err(e) => {
+            println!("❌  「{}」で分割できないため、行ごとに分割します。({e})", rule.name());
+            let splitter: Box<dyn Splitter> = match keep_line_endings {
+                true => Box::new(TerminatedLineSplitter),
+                false => Box::new(LineSplitter),
+            };
+            splitter
+        }
+    };
+    (splitter.split(text), None)
+}
+
+// 分割した行に列の選択・改行と空白の設定・フィルタ・上限を適用し、1回のコピーとしてキューに積む
+// recordsには行がレコードの場合にその区切り文字を渡す。sensitiveの場合はフィルタの後で機密の行として保持する。
+pub fn add_lines(
+    cb_data: &mut ClipboardData,
+    mut lines: Vec<String>,
+    records: Option<char>,
+    sensitive: bool,
+    mode: &RunMode,
+) -> Result<(FilterReport, LimitReport), (FilterReport, String)> {
+    let columns = mode.get_select_columns();
+    let transpose = mode.is_transpose();
+    // 列の選択と行列の入れ替え（変換後のセルには改行が無いので、元の改行は取り除いておく）
+    if !columns.is_empty() || transpose {
+        let delimiter = records
+            .or(mode.get_record_format().delimiter())
+            .unwrap_or('\t');
+        let mut table: Vec<String> = lines
+            .iter()
+            .map(|line| split_line_ending(line).0.to_owned())
+            .collect();
+        let transformed = transform_table(&table, delimiter, &columns, transpose);
+        // 機密の行の内容が残らないよう、置き換えた行はゼロクリアする
+        table.zeroize();
+        lines.zeroize();
+        lines = transformed;
+    }
+    // レコードの末尾に改行を付けるとフィールドの値に含まれてしまうので、レコードモードでは付けない
+    let line_ending = match records {
+        Some(_) => LineEnding::Strip,
+        None => mode.get_line_ending(),
+    };
+    let whitespace = mode.get_whitespace();
+    let finished = lines
+        .iter()
+        .map(|line| finish_entry(line, line_ending, whitespace))
+        .collect();
+    lines.zeroize();
+    let filter = mode.get_copy_filter();
+    let queue: HashSet<String> = match filter.dedupe {
+        Dedupe::Queue => cb_data
+            .entries()
+            .into_iter()
+            .map(|(_, line)| split_line_ending(line).0.to_owned())
+            .collect(),
+        _ => HashSet::new(),
+    };
+    let (mut lines, report) = filter.apply(finished, &queue);
+    if sensitive {
+        let expire_sec = mode.get_sensitive().expire_sec;
+        lines = lines
+            .into_iter()
+            .map(|line| secret::store(line, expire_sec))
+            .collect();
+        spawn_secret_purger();
+    }
+    match cb_data.add_copy(lines, &mode.get_queue_limit()) {
+        Ok(limit) => {
+            show_limit_warning(&limit);
+            Ok((report, limit))
         }
+        Err(e) => Err((report, e)),
+    }
+}
+
+// フィルタで除外した行数の内訳（除外しなかった場合は空）
+pub fn filter_summary(report: &FilterReport) -> String {
+    if report.dropped() == 0 {
+        return String::new();
+    }
+    format!(
+        "（除外 {} 行: 空行 {} / 除外パターン {} / 重複 {}）",
+        report.dropped(),
+        report.empty,
+        report.excluded,
+        report.duplicated
+    )
+}
+
+fn show_limit_warning(limit: &LimitReport) {
+    if limit.truncated > 0 {
+        println!("🟡  文字数の上限を超えた {} 行を切り詰めました。", limit.truncated);
+    }
+    if limit.dropped > 0 {
+        println!("🟡  キューの上限に達したため、{} 行を取り込みませんでした。", limit.dropped);
+    }
+    if limit.evicted > 0 {
+        println!("🟡  キューの上限に達したため、古い行を {} 行取り除きました。", limit.evicted);
     }
 }
 
 type EncodeFunc = unsafe extern "C" fn(*const u8, usize) -> EncodedString;
-unsafe fn paste_impl(cb: &mut ClipboardData) -> InputMode {
-    let s = cb.pop_back().unwrap();
-    // Encoderモディファイア（仮）を呼び出す。
-    let s = unsafe {
+// Encoderモディファイア（仮）を有効な順に呼び出す。
+// 機密の行の内容が残らないよう、途中の結果はすべてゼロクリアする。
+fn apply_text_modifiers(s: &str) -> Zeroizing<String> {
+    unsafe {
         let pm = TXT_MODIFIER.read().unwrap();
         let func_list =
             pm.get_all_plugin_func_with_order::<EncodeFunc>("do_encode", CallOrder::Asc);
 
-        let mut encoded = CString::new(s.clone()).unwrap().to_bytes().to_vec();
+        let mut encoded = Zeroizing::new(s.as_bytes().to_vec());
         for f in func_list {
             let e = f(encoded.as_ptr(), encoded.len());
-            encoded = e.to_vec();
+            encoded = Zeroizing::new(e.to_vec());
         }
-        match String::from_utf8(encoded) {
-            Ok(s) => s,
+        match String::from_utf8(std::mem::take(&mut *encoded)) {
+            Ok(s) => Zeroizing::new(s),
             Err(e) => {
-                println!("🔄  モディファイアによるエンコードに失敗したため、ロールバックします（返却値がUTF-8文字列ではありません / {e}）");
-                s
+                let reason = e.utf8_error();
+                e.into_bytes().zeroize();
+                println!("🔄  モディファイアによるエンコードに失敗したため、ロールバックします（返却値がUTF-8文字列ではありません / {reason}）");
+                Zeroizing::new(s.to_owned())
             }
         }
+    }
+}
+
+// CTRL+ALT+Vで呼び出された場合は、ALTキーを押したまま文字が入力されないよう離しておく
+// 離したキーを返すので、送信後にrestore_alt_keyで元に戻すこと。
+fn release_alt_key(kbd: &mut KeySequence) -> Vec<usize> {
+    let lmap = unsafe { KEY_MAP.read().unwrap() };
+    let mut released = Vec::new();
+    for vk in [VK_LMENU, VK_RMENU] {
+        if lmap[vk] {
+            kbd.push_key(vk, KeyAction::Up);
+            released.push(vk);
+        }
+    }
+    released
+}
+fn restore_alt_key(kbd: &mut KeySequence, released: Vec<usize>) {
+    let lmap = unsafe { KEY_MAP.read().unwrap() };
+    for vk in released {
+        if lmap[vk] {
+            kbd.push_key(vk, KeyAction::Down);
+        }
+    }
+}
+
+// レコードモードでは1レコードのフィールドを順に入力し、それぞれの後にフィールドの区切りを送信する
+// 機密の行の内容は、取り出してから入力し終えるまでZeroizingで保持する
+unsafe fn paste_entry(line: String, iclip: &mut ClipboardLock) -> InputMode {
+    // 機密の行はここで内容を取り出す
+    let sensitive = secret::is_token(&line);
+    let line = if sensitive {
+        match secret::reveal(&line) {
+            Some(text) => text,
+            None => {
+                println!("🔐  機密の行は期限切れのため破棄されています。");
+                return RUN_MODE.read().unwrap().get_input_mode();
+            }
+        }
+    } else {
+        Zeroizing::new(line)
     };
+    let (delimiter, input_mode, separator_keyseq, char_delay_msec) = {
+        let mode = RUN_MODE.read().unwrap();
+        (
+            mode.get_record_format().delimiter(),
+            mode.get_input_mode(),
+            mode.get_field_separator_keyseq(),
+            mode.get_char_delay_msec(),
+        )
+    };
+    let delimiter = match delimiter {
+        Some(delimiter) => delimiter,
+        None => return paste_impl(&line, sensitive, iclip),
+    };
+    let fields = Zeroizing::new(parse_record(&line, delimiter));
+    // クリップボード経由の場合はフィールドごとに入力できないので、タブ区切りの1行としてペーストする
+    if input_mode != InputMode::DirectKeyInput {
+        return paste_impl(&Zeroizing::new(fields.join("\t")), sensitive, iclip);
+    }
+    for field in fields.iter() {
+        let input_mode = paste_impl(field, sensitive, iclip);
+        if input_mode != InputMode::DirectKeyInput {
+            // 長すぎるフィールドでクリップボード経由に切り替わった場合は、残りのフィールドを入力しない
+            println!("❎  レコードの入力が中断されました。");
+            return input_mode;
+        }
+        // paste_implと同じく、区切りを送る間だけ制御キーを離しておく
+        let mut kbd = KeySequence::new();
+        kbd.set_delay(char_delay_msec);
+        let released_alt = release_alt_key(&mut kbd);
+        kbd.push_key(VK_LCONTROL, KeyAction::Up);
+        kbd.push_str(&separator_keyseq);
+        if KEY_MAP.read().unwrap()[VK_LCONTROL] {
+            kbd.push_key(VK_LCONTROL, KeyAction::Down);
+        }
+        restore_alt_key(&mut kbd, released_alt);
+        injector::send_keys(&kbd);
+    }
+    InputMode::DirectKeyInput
+}
+
+// sensitiveの場合、クリップボード経由では機密として指定して書き込む
+unsafe fn paste_impl(line: &str, sensitive: bool, iclip: &mut ClipboardLock) -> InputMode {
+    let s = apply_text_modifiers(line);
     let (input_mode, char_delay_msec, line_len_max) = {
         let mode = RUN_MODE.read().unwrap();
         (
@@ -695,12 +1160,9 @@ unsafe fn paste_impl(cb: &mut ClipboardData) -> InputMode {
     show_operation_message("ペースト");
     let input_mode = if s.len() > line_len_max && input_mode == InputMode::DirectKeyInput {
         let eh = unsafe { EH_CTL.read().unwrap() };
-        let mut lmap = unsafe { KEY_MAP.write().unwrap() };
-        let shift = VK_LSHIFT.0 as usize;
-        let old_shift = lmap[shift];
-        lmap[shift] = true;
-        eh['M' as usize](&lmap, EhKeyState::Alt);
-        lmap[shift] = old_shift;
+        let lmap = unsafe { KEY_MAP.read().unwrap() };
+        // CTRL+ALT+SHIFT+M と同じくクリップボード入力に切り替える
+        eh['M' as usize](&lmap, MOD_SHIFT, EhKeyState::Alt);
         InputMode::Clipboard
     } else {
         input_mode
@@ -713,86 +1175,291 @@ unsafe fn paste_impl(cb: &mut ClipboardData) -> InputMode {
         // 現在のキーボードの状況（KeyboardLLHookから取得した状況）に合わせて制御キーの解除と設定を行う。
         // その後に、ペースト対象のデータを送る
         // さらに、現在のキーボードの状況に合わせて今度は制御キーを復旧させる。
-        let mut kbd = Keyboard::new();
+        let mut kbd = KeySequence::new();
         // CTRLキーを一旦解除する
-        kbd.new_delay(char_delay_msec);
-        kbd.append_input_chain(
-            KeycodeBuilder::default()
-                .vk(VK_LCONTROL.0)
-                .scan_code(virtual_key_to_scancode(VK_LCONTROL))
-                .build(),
-        );
+        kbd.set_delay(char_delay_msec);
+        kbd.set_sensitive(sensitive);
+        let released_alt = release_alt_key(&mut kbd);
+        kbd.push_key(VK_LCONTROL, KeyAction::Press);
         // ペースト対象の文字列を登録する
-        for c in s.as_str().chars() {
-            KeycodeBuilder::default()
-                .char_build(char::from_u32(c as u32).unwrap())
-                .iter()
-                .for_each(|key_code| kbd.append_input_chain(key_code.clone()));
+        // キー入力ではCRとLFがそれぞれ改行として入力されるので、CRLFの行末はLFだけを送る
+        match split_line_ending(&s) {
+            (body, "\r\n") => {
+                kbd.push_str(body);
+                kbd.push_str("\n");
+            }
+            _ => kbd.push_str(&s),
         }
-        enable_ctrl_v();
-        kbd.send_key();
-        kbd.clear_input_chain();
+        hook::enable_ctrl_v();
+        injector::send_keys(&kbd);
+        kbd.clear();
         // CTRLキーが押されている状況をチェックしてチェーンに登録する
-        let mode = if is_key_pressed(162) {
-            KeySendMode::KeyDown
+        let mode = if is_key_pressed(VK_LCONTROL) {
+            KeyAction::Down
         } else {
-            KeySendMode::KeyUp
+            KeyAction::Up
         };
-        kbd.append_input_chain(
-            KeycodeBuilder::default()
-                .vk(VK_LCONTROL.0)
-                .scan_code(virtual_key_to_scancode(VK_LCONTROL))
-                .key_send_mode(mode)
-                .build(),
-        );
-        kbd.send_key();
+        kbd.push_key(VK_LCONTROL, mode);
+        restore_alt_key(&mut kbd, released_alt);
+        injector::send_keys(&kbd);
     } else {
         if s.len() == 0 {
             return input_mode;
         }
-        let data = OsString::from(s).encode_wide().collect::<Vec<u16>>();
-        let strdata_len = data.len() * 2;
-        let data_ptr = data.as_ptr();
-        let gdata = GlobalAlloc(GHND | GLOBAL_ALLOC_FLAGS(GMEM_SHARE), strdata_len + 2);
-        let locked_data = GlobalLock(gdata);
-        std::ptr::copy_nonoverlapping(
-            data_ptr as *const u8,
-            locked_data as *mut u8,
-            strdata_len + 2,
-        );
-        let _r = SetClipboardData(CF_UNICODETEXT.0, HANDLE(gdata));
-        // 終わったらアンロックしてからメモリを開放する
-        GlobalUnlock(gdata);
-        GlobalFree(gdata);
+        if sensitive {
+            iclip.set_sensitive_text(&s);
+        } else {
+            iclip.set_text(&s);
+        }
     }
     input_mode
 }
 
-fn virtual_key_to_scancode(vk: VIRTUAL_KEY) -> u16 {
-    unsafe { MapVirtualKeyA(vk.0 as u32, MAPVK_VK_TO_VSC as u32) as u16 }
-}
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use crate::clipboard::{self, MemoryClipboard};
+    use crate::hook::MockHookController;
+    use crate::injector::{InjectedKey, RecordingInjector};
+    use std::sync::MutexGuard;
 
-fn get_window_text(hwnd: HWND) -> String {
-    unsafe {
-        // GetWindowTextLengthW + GetWindowTextWは別プロセスへの取得を意図したものではないとの記述がMSDNにあるので
-        // SendMessageWで取得することにする。
-        let len = SendMessageW(hwnd, WM_GETTEXTLENGTH, WPARAM(0), LPARAM(0)).0 as usize + 1;
-        let mut buf = vec![0u16; len];
-        SendMessageW(
-            hwnd,
-            WM_GETTEXT,
-            WPARAM(len),
-            LPARAM(buf.as_mut_ptr() as isize),
-        );
-        OsString::from_wide(&buf[0..buf.len() - 1])
-            .to_os_string()
-            .into_string()
-            .unwrap()
+    // エンジンの状態はプロセス全体で共有されるので、エンジンを使うテストは1つずつ実行する
+    static ENGINE_LOCK: Mutex<()> = Mutex::new(());
+
+    pub struct Engine {
+        pub clipboard: MemoryClipboard,
+        pub keys: RecordingInjector,
+        _lock: MutexGuard<'static, ()>,
+    }
+    // モックのバックエンドと指定した設定でエンジンを初期化し、キューなどの状態を空にする
+    pub fn setup(mut config: Config, input_mode: InputMode) -> Engine {
+        let lock = ENGINE_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+        let cb = MemoryClipboard::new();
+        let keys = RecordingInjector::new();
+        clipboard::set_clipboard_backend(Box::new(cb.clone()));
+        injector::set_key_injector(Box::new(keys.clone()));
+        hook::set_hook_controller(Box::new(MockHookController::new()));
+        let mut mode = RunMode::new();
+        mode.set_input_mode(input_mode);
+        config.check().unwrap();
+        mode.set_config(config);
+        set_mode(mode);
+        unsafe {
+            if EH_CTL.read().unwrap().is_empty() {
+                eh_init();
+            }
+            *CLIPBOARD.lock().unwrap() = Registers::new();
+            *LAST_PASTED.lock().unwrap() = None;
+            *LAST_COPY_TEXT.lock().unwrap() = None;
+            *LAST_COPY_SEQUENCE.lock().unwrap() = None;
+            KEY_MAP.write().unwrap().iter_mut().for_each(|key| *key = false);
+        }
+        Engine {
+            clipboard: cb,
+            keys,
+            _lock: lock,
+        }
+    }
+    impl Engine {
+        // 他のアプリケーションがクリップボードにコピーした内容を取り込む
+        pub fn copy(&self, text: &str) {
+            self.clipboard.copy_from_outside(text);
+            async_std::task::block_on(copy_clipboard());
+        }
+        // パスワードマネージャーが機密としてコピーした内容を取り込む
+        pub fn copy_secret(&self, text: &str) {
+            self.clipboard.copy_secret_from_outside(text);
+            async_std::task::block_on(copy_clipboard());
+        }
+        pub fn paste(&self) {
+            let cb_lock_wait = Arc::new((Mutex::new(false), Condvar::new()));
+            async_std::task::block_on(paste(cb_lock_wait, PasteSource::Queue));
+        }
+        pub fn key_down(&self, vk: usize) {
+            key_down(0, &KeyEvent::new(vk));
+        }
+        pub fn writes(&self) -> Vec<String> {
+            let state = self.clipboard.state();
+            state.writes.iter().map(|write| write.text.clone()).collect()
+        }
+        pub fn queue(&self) -> Vec<String> {
+            with_active_queue(|queue| queue.entries().iter().map(|(_, line)| (*line).clone()).collect())
+        }
+        // キー入力として送信された文字列
+        pub fn typed(&self) -> String {
+            let units: Vec<u16> = self
+                .keys
+                .events()
+                .iter()
+                .filter_map(|event| match event {
+                    InjectedKey::Down { vk: 0, scan_code } => Some(*scan_code),
+                    InjectedKey::SecretChar(scan_code) => Some(*scan_code),
+                    _ => None,
+                })
+                .collect();
+            String::from_utf16_lossy(&units)
+        }
+    }
+
+    #[test]
+    fn copied_lines_are_pasted_through_the_clipboard_in_order() {
+        let engine = setup(Config::default(), InputMode::Clipboard);
+        engine.copy("first\r\nsecond\nthird");
+        assert_eq!(engine.queue(), ["third", "second", "first"]);
+        for _ in 0..3 {
+            engine.paste();
+        }
+        assert_eq!(engine.writes(), ["first", "second", "third"]);
+        assert!(engine.queue().is_empty());
+        // 空のキューでペーストしてもクリップボードには書き込まない
+        engine.paste();
+        assert_eq!(engine.writes().len(), 3);
+    }
+
+    #[test]
+    fn copied_lines_are_typed_in_direct_key_input_mode() {
+        let engine = setup(Config::default(), InputMode::DirectKeyInput);
+        engine.copy("abc\ndef");
+        engine.paste();
+        assert_eq!(engine.typed(), "abc");
+        assert!(engine.writes().is_empty());
+        assert_eq!(engine.queue(), ["def"]);
+    }
+
+    #[test]
+    fn secret_lines_are_queued_as_tokens_and_typed_when_pasted() {
+        let mut config = Config::default();
+        config.sensitive.on_sensitive = OnSensitive::Flag;
+        config.record_format = crate::splitter::RecordFormat::Tsv;
+        config.field_separator_key = "".to_owned();
+        let engine = setup(config, InputMode::DirectKeyInput);
+        engine.copy_secret("user\t\"p\"\"w\"\r\nnext");
+        assert!(engine.queue().iter().all(|line| secret::is_token(line)));
+        engine.paste();
+        assert_eq!(engine.typed(), "userp\"w");
+        // 機密の行の文字は伏せて記録される
+        assert!(!engine
+            .keys
+            .events()
+            .iter()
+            .any(|event| matches!(event, InjectedKey::Down { vk: 0, .. })));
+        assert!(engine.writes().is_empty());
+    }
+
+    #[test]
+    fn secret_lines_are_written_to_the_clipboard_as_sensitive() {
+        let mut config = Config::default();
+        config.sensitive.on_sensitive = OnSensitive::Flag;
+        let engine = setup(config, InputMode::Clipboard);
+        engine.copy_secret("pw");
+        engine.copy("plain");
+        engine.paste();
+        assert!(engine.clipboard.state().sensitive);
+        engine.paste();
+        assert!(!engine.clipboard.state().sensitive);
+        assert_eq!(engine.writes(), ["pw", "plain"]);
+    }
+
+    #[test]
+    fn undo_removes_the_last_copy_before_it_is_pasted() {
+        let engine = setup(Config::default(), InputMode::Clipboard);
+        engine.copy("a\nb");
+        engine.copy("c");
+        async_std::task::block_on(undo_clipboard());
+        engine.paste();
+        engine.paste();
+        engine.paste();
+        assert_eq!(engine.writes(), ["a", "b"]);
+    }
+
+    fn down(vk: usize) -> InjectedKey {
+        InjectedKey::Down {
+            vk,
+            scan_code: vk_to_scancode(vk),
+        }
+    }
+    fn up(vk: usize) -> InjectedKey {
+        InjectedKey::Up {
+            vk,
+            scan_code: vk_to_scancode(vk),
+        }
+    }
+    fn char_press(c: char) -> [InjectedKey; 2] {
+        let scan_code = c as u16;
+        [
+            InjectedKey::Down { vk: 0, scan_code },
+            InjectedKey::Up { vk: 0, scan_code },
+        ]
     }
-}
 
-pub unsafe fn u16_ptr_to_string(ptr: *const u16) -> OsString {
-    let len = (0..).take_while(|&i| *ptr.offset(i) != 0).count();
-    let slice = std::slice::from_raw_parts(ptr, len);
-    OsString::from_wide(slice)
+    #[test]
+    fn burst_paste_releases_and_restores_ctrl_around_each_line() {
+        let config = Config {
+            tabindex_key: "\t".to_owned(),
+            line_delay_msec: 30,
+            ..Config::default()
+        };
+        let engine = setup(config, InputMode::DirectKeyInput);
+        let mut mode = get_mode();
+        mode.set_burst_mode(true);
+        set_mode(mode);
+        engine.copy("a\nb\nc");
+        // Ctrl+Vを押している間にペーストされる
+        engine.key_down(VK_LCONTROL);
+        engine.paste();
+
+        let mut expected = Vec::new();
+        for c in ['a', 'b', 'c'] {
+            // CTRLを離してから入力し、押されたままの状態に戻す
+            expected.extend([down(VK_LCONTROL), up(VK_LCONTROL)]);
+            expected.extend(char_press(c));
+            expected.push(down(VK_LCONTROL));
+            // 次の入力欄へ移動する
+            expected.extend([down(VK_LCONTROL), up(VK_LCONTROL)]);
+            expected.extend(char_press('\t'));
+            expected.push(InjectedKey::Delay(30));
+        }
+        assert_eq!(engine.keys.take_events(), expected);
+        assert!(engine.queue().is_empty());
+    }
+
+    #[test]
+    fn ctrl_stays_released_when_it_is_not_held() {
+        let engine = setup(Config::default(), InputMode::DirectKeyInput);
+        engine.copy("x");
+        engine.paste();
+        let mut expected = vec![down(VK_LCONTROL), up(VK_LCONTROL)];
+        expected.extend(char_press('x'));
+        expected.push(up(VK_LCONTROL));
+        assert_eq!(engine.keys.take_events(), expected);
+    }
+
+    #[test]
+    fn hotkey_changes_the_rule_for_the_next_copy_only() {
+        let engine = setup(Config::default(), InputMode::Clipboard);
+        engine.key_down(VK_LCONTROL);
+        engine.key_down(VK_LMENU);
+        engine.key_down('L' as usize);
+        engine.copy("a\nb\n\nc");
+        assert_eq!(engine.queue(), ["c", "a\nb"]);
+        engine.copy("d\ne");
+        assert_eq!(engine.queue(), ["e", "d", "c", "a\nb"]);
+    }
+
+    #[test]
+    fn csv_file_is_re_encoded_with_the_configured_delimiter() {
+        let config = Config {
+            record_format: crate::splitter::RecordFormat::Tsv,
+            ..Config::default()
+        };
+        let engine = setup(config, InputMode::DirectKeyInput);
+        let path = std::env::temp_dir().join(format!("import-{}.csv", std::process::id()));
+        std::fs::write(&path, "a,\"b,c\"\r\nd,\"e\tf\"\r\n").unwrap();
+        crate::importer::import_file(&path.to_string_lossy(), &get_mode()).unwrap();
+        let _ = std::fs::remove_file(&path);
+        assert_eq!(engine.queue(), ["d\t\"e\tf\"", "a\tb,c"]);
+        for record in engine.queue() {
+            assert_eq!(parse_record(&record, '\t').len(), 2);
+        }
+    }
 }
@@ -1,3 +1,5 @@
+use crate::keycode::KeyEvent;
+#[cfg(feature = "win32")]
 use ::windows::Win32::UI::WindowsAndMessaging::KBDLLHOOKSTRUCT;
 use multiline_parser_pluginlib::result::*;
 use notify::event::{DataChange, ModifyKind};
@@ -6,19 +8,32 @@ use std::path::Path;
 use std::sync::Mutex;
 use toolbox::config_loader::ConfigLoader;
 #[no_mangle]
+pub extern "C" fn key_down_event(keystate: u32, event: KeyEvent) -> PluginResult {
+    crate::default::key_down(keystate, &event)
+}
+
+#[no_mangle]
+pub extern "C" fn key_up_event(keystate: u32, event: KeyEvent) -> PluginResult {
+    crate::default::key_up(keystate, &event)
+}
+
+// 現行のWindowsホスト向けの互換関数
+#[cfg(feature = "win32")]
+#[no_mangle]
 pub extern "C" fn key_down(keystate: u32, stroke_msg: KBDLLHOOKSTRUCT) -> PluginResult {
-    crate::default::key_down(keystate, stroke_msg)
+    crate::default::key_down(keystate, &crate::platform::to_key_event(&stroke_msg))
 }
 
+#[cfg(feature = "win32")]
 #[no_mangle]
 pub extern "C" fn key_up(keystate: u32, stroke_msg: KBDLLHOOKSTRUCT) -> PluginResult {
-    crate::default::key_up(keystate, stroke_msg)
+    crate::default::key_up(keystate, &crate::platform::to_key_event(&stroke_msg))
 }
 use crate::config::get_config_path;
 use crate::default::{get_mode, set_mode};
 use notify::*;
 static mut EVENT_CHATTER: Lazy<Mutex<usize>> = Lazy::new(|| Mutex::new(0));
-static mut CONFIG_WATCHER: Lazy<Mutex<ReadDirectoryChangesWatcher>> = Lazy::new(|| {
+static mut CONFIG_WATCHER: Lazy<Mutex<RecommendedWatcher>> = Lazy::new(|| {
     Mutex::new(
         notify::recommended_watcher(|res: std::result::Result<Event, Error>| match res {
             Ok(event) => {
@@ -65,7 +80,11 @@ async fn wait_flush() {
         if *chatter_cnt == 0 {
             // 100ms（チャタリング判定時間）以内に到達したイベントの一番最後なので設定ファイルをロードする
             let mut mode = get_mode();
-            let config: crate::config::Config = ConfigLoader::load_file(&get_config_path());
+            let mut config: crate::config::Config = ConfigLoader::load_file(&get_config_path());
+            if let Err(e) = config.check() {
+                println!("❌  設定ファイルが不正なため、リロードしませんでした。({e})");
+                return;
+            }
             mode.set_config(config.clone());
             set_mode(mode);
             println!("🔄  設定ファイルをリロードしました。");
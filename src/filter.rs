@@ -0,0 +1,158 @@
+// コピーした行をキューに積む前に取り除くフィルタ
+// 空白の除去は whitespace の設定で分割時に行うので、ここでは行を残すかどうかだけを決める。
+use crate::splitter::split_line_ending;
+use regex::Regex;
+use serde_derive::{Deserialize, Serialize};
+use std::collections::hash_map::RandomState;
+use std::collections::HashSet;
+use std::hash::{BuildHasher, Hash, Hasher};
+use zeroize::Zeroize;
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum Dedupe {
+    #[default]
+    None,
+    // 同じコピーの中で重複した行
+    Group,
+    // キューに既にある行も含めて重複した行
+    Queue,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Default)]
+#[serde(default)]
+pub struct CopyFilter {
+    // 空行を取り除く
+    pub skip_empty: bool,
+    // いずれかの正規表現に一致する行を取り除く（例: "^#"）
+    pub exclude_patterns: Vec<String>,
+    pub dedupe: Dedupe,
+    // 同じ内容を続けてコピーした場合は2回目を無視する
+    pub skip_repeated_copy: bool,
+    // 設定の読み込み時にcompileでコンパイルしたexclude_patterns
+    #[serde(skip)]
+    compiled: CompiledPatterns,
+}
+
+#[derive(Debug, Clone, Default)]
+struct CompiledPatterns(Vec<Regex>);
+impl PartialEq for CompiledPatterns {
+    fn eq(&self, other: &Self) -> bool {
+        self.0.iter().map(Regex::as_str).eq(other.0.iter().map(Regex::as_str))
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct FilterReport {
+    pub kept: usize,
+    pub empty: usize,
+    pub excluded: usize,
+    pub duplicated: usize,
+}
+impl FilterReport {
+    pub fn dropped(&self) -> usize {
+        self.empty + self.excluded + self.duplicated
+    }
+}
+
+impl CopyFilter {
+    // 除外パターンをコンパイルする。不正なパターンがある場合はそのパターンを含めた理由を返す
+    pub fn compile(&mut self) -> Result<(), String> {
+        let patterns = self
+            .exclude_patterns
+            .iter()
+            .map(|pattern| {
+                Regex::new(pattern).map_err(|e| {
+                    format!("copy_filter.exclude_patterns の {pattern:?} が不正です: {e}")
+                })
+            })
+            .collect::<Result<_, _>>()?;
+        self.compiled = CompiledPatterns(patterns);
+        Ok(())
+    }
+    // queueにはDedupe::Queueの場合に比較するキューの行を渡す
+    pub fn apply(
+        &self,
+        lines: Vec<String>,
+        queue: &HashSet<String>,
+    ) -> (Vec<String>, FilterReport) {
+        let patterns = &self.compiled.0;
+        // 機密の行の内容を残さないよう、重複の判定には内容の代わりにハッシュ値を保持する
+        let hasher = RandomState::new();
+        let mut seen: HashSet<u64> = match self.dedupe {
+            Dedupe::Queue => queue.iter().map(|line| hash_text(&hasher, line)).collect(),
+            _ => HashSet::new(),
+        };
+        let mut report = FilterReport::default();
+        let mut kept = Vec::new();
+        for mut line in lines {
+            let (body, _) = split_line_ending(&line);
+            let dropped = if self.skip_empty && body.is_empty() {
+                &mut report.empty
+            } else if patterns.iter().any(|regex| regex.is_match(body)) {
+                &mut report.excluded
+            } else if self.dedupe != Dedupe::None && !seen.insert(hash_text(&hasher, body)) {
+                &mut report.duplicated
+            } else {
+                kept.push(line);
+                continue;
+            };
+            *dropped += 1;
+            line.zeroize();
+        }
+        report.kept = kept.len();
+        (kept, report)
+    }
+}
+
+fn hash_text(hasher: &RandomState, text: &str) -> u64 {
+    let mut hasher = hasher.build_hasher();
+    text.hash(&mut hasher);
+    hasher.finish()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn lines(lines: &[&str]) -> Vec<String> {
+        lines.iter().map(|line| (*line).to_owned()).collect()
+    }
+
+    #[test]
+    fn each_filter_drops_and_counts_lines() {
+        let mut filter = CopyFilter {
+            skip_empty: true,
+            exclude_patterns: vec!["^#".to_owned(), "tmp$".to_owned()],
+            dedupe: Dedupe::Group,
+            ..CopyFilter::default()
+        };
+        filter.compile().unwrap();
+        let copied = lines(&["a\n", "\n", "# note\n", "b\n", "a\n", "x.tmp\n", "c"]);
+        let (kept, report) = filter.apply(copied, &HashSet::new());
+        assert_eq!(kept, ["a\n", "b\n", "c"]);
+        assert_eq!((report.kept, report.empty, report.excluded, report.duplicated), (3, 1, 2, 1));
+    }
+
+    #[test]
+    fn queue_dedupe_compares_with_lines_already_queued() {
+        let filter = CopyFilter {
+            dedupe: Dedupe::Queue,
+            ..CopyFilter::default()
+        };
+        let queue = HashSet::from(["a".to_owned()]);
+        let (kept, report) = filter.apply(lines(&["a", "b", "b"]), &queue);
+        assert_eq!(kept, ["b"]);
+        assert_eq!(report.duplicated, 2);
+    }
+
+    #[test]
+    fn invalid_pattern_is_reported_when_compiling() {
+        let mut filter = CopyFilter {
+            exclude_patterns: vec!["ok".to_owned(), "(".to_owned()],
+            ..CopyFilter::default()
+        };
+        let e = filter.compile().unwrap_err();
+        assert!(e.contains("exclude_patterns") && e.contains("\"(\""), "{e}");
+    }
+}
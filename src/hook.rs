@@ -0,0 +1,94 @@
+use once_cell::unsync::*;
+use std::sync::{Arc, Mutex};
+
+// キーボードフックの開始と、OSによるCtrl+Vの貼り付けの抑止を制御するバックエンド
+pub trait HookController: Send {
+    // 開始できなかった場合は理由を返す
+    fn set_hook(&mut self) -> Result<(), String>;
+    // Ctrl+Vの抑止ができるかどうか
+    fn can_suppress_ctrl_v(&self) -> bool;
+    fn suppress_ctrl_v(&mut self);
+    fn release_ctrl_v(&mut self);
+}
+
+static mut HOOK_CONTROLLER: Lazy<Mutex<Box<dyn HookController>>> =
+    Lazy::new(|| Mutex::new(crate::platform::default_hook_controller()));
+
+pub fn set_hook_controller(controller: Box<dyn HookController>) {
+    let mut h = unsafe { HOOK_CONTROLLER.lock().unwrap() };
+    *h = controller;
+}
+pub fn sethook() {
+    let mut h = unsafe { HOOK_CONTROLLER.lock().unwrap() };
+    if let Err(e) = h.set_hook() {
+        println!("🟡  キーボードフックを開始できませんでした。({e})");
+    }
+    if !h.can_suppress_ctrl_v() {
+        println!("🟡  縮退モードで動作します。Ctrl+Vを抑止できないため、ペーストはバーストモードでのみ行われます。");
+        println!("🟡  通常モードのCtrl+VはOSの既定の動作になります。");
+    }
+}
+pub fn can_suppress_ctrl_v() -> bool {
+    let h = unsafe { HOOK_CONTROLLER.lock().unwrap() };
+    h.can_suppress_ctrl_v()
+}
+// Ctrl+Vの無効化
+pub fn disable_ctrl_v() {
+    let mut h = unsafe { HOOK_CONTROLLER.lock().unwrap() };
+    h.suppress_ctrl_v();
+}
+// Ctrl+Vの有効化
+pub fn enable_ctrl_v() {
+    let mut h = unsafe { HOOK_CONTROLLER.lock().unwrap() };
+    h.release_ctrl_v();
+}
+
+// フックもCtrl+Vの抑止も使用できない場合の縮退モード
+// キーイベントはホストのフックから通知されるものだけを使い、握りつぶせるのはバーストモードのコンボキーのみとなる。
+pub struct DegradedHookController {
+    reason: String,
+}
+impl DegradedHookController {
+    pub fn new(reason: String) -> Self {
+        DegradedHookController { reason }
+    }
+}
+impl HookController for DegradedHookController {
+    fn set_hook(&mut self) -> Result<(), String> {
+        Err(self.reason.clone())
+    }
+    fn can_suppress_ctrl_v(&self) -> bool {
+        false
+    }
+    fn suppress_ctrl_v(&mut self) {}
+    fn release_ctrl_v(&mut self) {}
+}
+
+// Ctrl+Vを抑止しているかどうかを記録するだけのバックエンド
+// cloneしたものは状態を共有する。
+#[derive(Clone, Default)]
+pub struct MockHookController {
+    suppressed: Arc<Mutex<bool>>,
+}
+impl MockHookController {
+    pub fn new() -> Self {
+        MockHookController::default()
+    }
+    pub fn is_ctrl_v_suppressed(&self) -> bool {
+        *self.suppressed.lock().unwrap()
+    }
+}
+impl HookController for MockHookController {
+    fn set_hook(&mut self) -> Result<(), String> {
+        Ok(())
+    }
+    fn can_suppress_ctrl_v(&self) -> bool {
+        true
+    }
+    fn suppress_ctrl_v(&mut self) {
+        *self.suppressed.lock().unwrap() = true;
+    }
+    fn release_ctrl_v(&mut self) {
+        *self.suppressed.lock().unwrap() = false;
+    }
+}
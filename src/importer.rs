@@ -0,0 +1,83 @@
+// ファイルや標準入力の内容を、1回のコピーとして選択中のレジスタに取り込む
+// 拡張子が .csv / .tsv の場合はレコード、.json の場合は配列の要素ごと、それ以外はコピーと同じ方法で分割する。
+use crate::config::RunMode;
+use crate::default::{add_lines, filter_summary, split_text, with_queue};
+use crate::splitter::{encode_record, parse_record, RecordSplitter, Splitter};
+use serde_json::Value;
+use std::io::Read;
+use std::path::Path;
+
+// "-" の場合は標準入力から読み込む
+pub fn import_file(path: &str, mode: &RunMode) -> Result<(), String> {
+    let text = if path == "-" {
+        let mut text = String::new();
+        std::io::stdin()
+            .read_to_string(&mut text)
+            .map_err(|e| e.to_string())?;
+        text
+    } else {
+        std::fs::read_to_string(path).map_err(|e| e.to_string())?
+    };
+    let extension = Path::new(path)
+        .extension()
+        .map(|ext| ext.to_string_lossy().to_lowercase())
+        .unwrap_or_default();
+    let (lines, records) = match extension.as_str() {
+        "csv" => file_records(&text, ',', mode),
+        "tsv" => file_records(&text, '\t', mode),
+        "json" => {
+            let delimiter = mode.get_record_format().delimiter().unwrap_or('\t');
+            (json_lines(&text, delimiter)?, None)
+        }
+        _ => split_text(&text, mode),
+    };
+    let (filter, limit) = with_queue(&mode.get_register_name(), |queue| {
+        add_lines(queue, lines, records, false, mode)
+    })
+    .map_err(|(_, e)| e)?;
+    println!(
+        "📥  {} から {} 行取り込みました{}",
+        if path == "-" { "標準入力" } else { path },
+        limit.added,
+        filter_summary(&filter)
+    );
+    Ok(())
+}
+
+// ファイルをレコードに分割する。ペーストする際はrecord_formatの区切りでフィールドに分けるので、
+// ファイルの区切りと異なる場合はrecord_formatの区切りで書き直す。
+fn file_records(text: &str, file_delimiter: char, mode: &RunMode) -> (Vec<String>, Option<char>) {
+    let delimiter = mode.get_record_format().delimiter().unwrap_or(file_delimiter);
+    let mut records = RecordSplitter::new(file_delimiter).split(text);
+    if delimiter != file_delimiter {
+        for record in records.iter_mut() {
+            *record = encode_record(&parse_record(record, file_delimiter), delimiter);
+        }
+    }
+    (records, Some(delimiter))
+}
+
+// JSONの配列を要素ごとの行にする。配列の要素が配列の場合はレコードとして扱う。
+fn json_lines(text: &str, delimiter: char) -> Result<Vec<String>, String> {
+    let values = match serde_json::from_str(text).map_err(|e| e.to_string())? {
+        Value::Array(values) => values,
+        _ => return Err("JSONの配列ではありません".to_owned()),
+    };
+    Ok(values
+        .into_iter()
+        .map(|value| match value {
+            Value::Array(fields) => {
+                let fields: Vec<String> = fields.into_iter().map(json_text).collect();
+                encode_record(&fields, delimiter)
+            }
+            value => json_text(value),
+        })
+        .collect())
+}
+fn json_text(value: Value) -> String {
+    match value {
+        Value::String(s) => s,
+        Value::Null => String::new(),
+        value => value.to_string(),
+    }
+}
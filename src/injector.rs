@@ -0,0 +1,107 @@
+use crate::keycode::*;
+use once_cell::unsync::*;
+use std::sync::{Arc, Mutex, MutexGuard};
+
+// キーストロークを送信するバックエンド
+pub trait KeyInjector: Send {
+    fn send(&mut self, seq: &KeySequence);
+    // 行間の待機時間など、キーストロークの送信とは別の待機
+    fn wait(&mut self, msec: u64) {
+        std::thread::sleep(std::time::Duration::from_millis(msec));
+    }
+}
+
+static mut KEY_INJECTOR: Lazy<Mutex<Box<dyn KeyInjector>>> =
+    Lazy::new(|| Mutex::new(crate::platform::default_key_injector()));
+
+pub fn set_key_injector(injector: Box<dyn KeyInjector>) {
+    let mut i = unsafe { KEY_INJECTOR.lock().unwrap() };
+    *i = injector;
+}
+pub fn send_keys(seq: &KeySequence) {
+    let mut i = unsafe { KEY_INJECTOR.lock().unwrap() };
+    i.send(seq);
+}
+pub fn wait(msec: u64) {
+    let mut i = unsafe { KEY_INJECTOR.lock().unwrap() };
+    i.wait(msec);
+}
+
+// キーストロークを送信しないバックエンド
+// キー入力のバックエンドが無い環境で使用する。送信しようとしたキーストロークは記録せずに捨てる。
+pub struct NullInjector;
+impl KeyInjector for NullInjector {
+    fn send(&mut self, _seq: &KeySequence) {}
+    fn wait(&mut self, _msec: u64) {}
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum InjectedKey {
+    Down { vk: usize, scan_code: u16 },
+    Up { vk: usize, scan_code: u16 },
+    // 機密の行の文字（押して離す）。表示する際は伏せる
+    SecretChar(u16),
+    Delay(u64),
+}
+
+// 送信されたキーストロークを記録するだけのバックエンド
+// 文字はvk=0、スキャンコードにUTF-16の値を入れたUnicode入力として記録する。
+// 機密の行の文字はSecretCharとして記録する。
+// cloneしたものは記録を共有する。
+#[derive(Clone, Default)]
+pub struct RecordingInjector {
+    events: Arc<Mutex<Vec<InjectedKey>>>,
+}
+impl RecordingInjector {
+    pub fn new() -> Self {
+        RecordingInjector::default()
+    }
+    pub fn events(&self) -> MutexGuard<'_, Vec<InjectedKey>> {
+        self.events.lock().unwrap()
+    }
+    pub fn take_events(&self) -> Vec<InjectedKey> {
+        std::mem::take(&mut *self.events.lock().unwrap())
+    }
+}
+impl KeyInjector for RecordingInjector {
+    fn send(&mut self, seq: &KeySequence) {
+        let mut events = self.events.lock().unwrap();
+        for (i, stroke) in seq.strokes().iter().enumerate() {
+            if i != 0 && seq.get_delay() != 0 {
+                events.push(InjectedKey::Delay(seq.get_delay()));
+            }
+            match stroke {
+                KeyStroke::Key(vk, action) => {
+                    let scan_code = vk_to_scancode(*vk);
+                    if *action != KeyAction::Up {
+                        events.push(InjectedKey::Down { vk: *vk, scan_code });
+                    }
+                    if *action != KeyAction::Down {
+                        events.push(InjectedKey::Up { vk: *vk, scan_code });
+                    }
+                }
+                KeyStroke::Char(c) => {
+                    let mut buf = [0u16; 2];
+                    for unit in c.encode_utf16(&mut buf) {
+                        if seq.is_sensitive() {
+                            events.push(InjectedKey::SecretChar(*unit));
+                            continue;
+                        }
+                        events.push(InjectedKey::Down {
+                            vk: 0,
+                            scan_code: *unit,
+                        });
+                        events.push(InjectedKey::Up {
+                            vk: 0,
+                            scan_code: *unit,
+                        });
+                    }
+                }
+            }
+        }
+    }
+    fn wait(&mut self, msec: u64) {
+        // 実際には待たない
+        self.events.lock().unwrap().push(InjectedKey::Delay(msec));
+    }
+}
@@ -0,0 +1,171 @@
+// コンソールで選択中のレジスタのキューを確認・編集する
+// フックの処理を止めないよう別スレッドで標準入力からコマンドを読み、コマンドごとにキューをロックする。
+use crate::default::{get_mode, with_active_queue};
+use crate::queue::{ClipboardData, QueueOperation};
+use crate::secret;
+use crate::snapshot;
+use crate::splitter::{parse_columns, transform_table};
+use once_cell::unsync::*;
+use std::io::BufRead;
+use std::sync::Mutex;
+
+static mut INSPECTOR_OPEN: Lazy<Mutex<bool>> = Lazy::new(|| Mutex::new(false));
+
+const HELP: &str = "\
+  l              一覧を表示
+  d <n>          n番目の行を削除
+  e <n> <text>   n番目の行を書き換え
+  m <n> <to>     n番目の行をto番目に移動
+  c <n>          n番目の行を複製
+  p <n>          n番目の行を次にペーストする
+  r              コピー単位ごとに行を逆順にする
+  s              コピー単位ごとに行を並べ替える
+  x              コピー単位ごとに行をシャッフルする
+  u              重複した行を取り除く
+  k <n>[,<n>..]  最後にコピーした表からn列目だけを残す
+  t              最後にコピーした表の行と列を入れ替える
+  w <path>       キューをスナップショット（JSON/CSV）に書き出す
+  o <path>       スナップショットからキューを復元する
+  h              このヘルプを表示
+  q              インスペクタを閉じる";
+
+pub fn spawn_inspector() {
+    {
+        let mut open = unsafe { INSPECTOR_OPEN.lock().unwrap() };
+        if *open {
+            println!("🔍  インスペクタは既に開いています。");
+            return;
+        }
+        *open = true;
+    }
+    std::thread::spawn(|| {
+        run();
+        let mut open = unsafe { INSPECTOR_OPEN.lock().unwrap() };
+        *open = false;
+    });
+}
+
+fn run() {
+    println!("🔍  キューのインスペクタを開きました。コマンド一覧:\n{HELP}");
+    with_active_queue(show);
+    for line in std::io::stdin().lock().lines() {
+        let line = match line {
+            Ok(line) => line,
+            Err(_) => break,
+        };
+        let line = line.trim();
+        if line.is_empty() {
+            continue;
+        }
+        if line == "q" {
+            break;
+        }
+        if let Err(e) = with_active_queue(|queue| execute(queue, line)) {
+            println!("❌  {e}");
+        }
+    }
+    println!("🔍  インスペクタを閉じました。");
+}
+
+// 一覧の番号は古い順でペーストされる順（1が次にペーストされる行）
+fn show(queue: &mut ClipboardData) {
+    let entries = queue.entries();
+    println!(
+        "📋  レジスタ「{}」のキュー（{}行）",
+        get_mode().get_register_name(),
+        entries.len()
+    );
+    // スティッキーモードではペーストされる行に印を付ける
+    let cursor = if get_mode().is_sticky_mode() {
+        Some(queue.get_cursor())
+    } else {
+        None
+    };
+    let mut last_group = None;
+    for (no, (group, line)) in entries.iter().rev().enumerate() {
+        if no == 0 || *group != last_group {
+            match group {
+                Some(group) => println!("  ── コピー {} ──", group + 1),
+                None => println!("  ── 未確定 ──"),
+            }
+            last_group = *group;
+        }
+        let mark = if cursor == Some(no) { "▶" } else { " " };
+        if secret::is_token(line) {
+            println!("{mark} {:>4}: {}", no + 1, secret::MASK);
+        } else {
+            println!("{mark} {:>4}: {:?}", no + 1, line);
+        }
+    }
+}
+
+fn execute(queue: &mut ClipboardData, command: &str) -> Result<(), String> {
+    let (name, args) = command.split_once(' ').unwrap_or((command, ""));
+    let len = queue.get_clipboard_lines();
+    // 表示上の番号をキューの先頭からの位置に変換する
+    let index = |no: &str| -> Result<usize, String> {
+        match no.trim().parse::<usize>() {
+            Ok(no) if 1 <= no && no <= len => Ok(len - no),
+            _ => Err(format!("1から{len}までの番号を指定してください。")),
+        }
+    };
+    match name {
+        "l" => {}
+        "h" => println!("{HELP}"),
+        "d" => {
+            queue.delete_at(index(args)?);
+        }
+        "e" => {
+            let (no, text) = args.split_once(' ').unwrap_or((args, ""));
+            queue.set_at(index(no)?, text.to_owned());
+        }
+        "m" => {
+            let (no, to) = args.split_once(' ').unwrap_or((args, ""));
+            queue.move_to(index(no)?, index(to)?);
+        }
+        "c" => {
+            queue.duplicate_at(index(args)?);
+        }
+        "p" => {
+            let mode = get_mode();
+            queue.move_to_next(index(args)?, mode.get_queue_order(), mode.is_sticky_mode());
+        }
+        "r" => queue.reorder_groups(QueueOperation::Reverse),
+        "s" => queue.reorder_groups(QueueOperation::Sort),
+        "x" => queue.reorder_groups(QueueOperation::Shuffle),
+        "k" => transform_newest_group(queue, &parse_columns(args)?, false)?,
+        "t" => transform_newest_group(queue, &[], true)?,
+        "w" => {
+            let snapshot = snapshot::take_snapshot(queue, &get_mode().get_register_name());
+            snapshot::export(args.trim(), &snapshot)?;
+            println!("📤  {} 行を {} に書き出しました。", snapshot.entries.len(), args.trim());
+        }
+        "o" => {
+            let lines = snapshot::restore(queue, snapshot::load(args.trim())?);
+            println!("📥  {} から {lines} 行を復元しました。", args.trim());
+        }
+        "u" => {
+            let removed = queue.unique();
+            println!("🧹  重複した行を{removed}行取り除きました。");
+        }
+        _ => return Err(format!("不明なコマンドです: {name}（hでヘルプを表示）")),
+    }
+    if name != "h" {
+        show(queue);
+    }
+    Ok(())
+}
+
+// 最後にコピーした単位を表として（レコードモードの形式、指定が無ければTSVで）解析し直す
+fn transform_newest_group(
+    queue: &mut ClipboardData,
+    columns: &[usize],
+    transpose: bool,
+) -> Result<(), String> {
+    let delimiter = get_mode().get_record_format().delimiter().unwrap_or('\t');
+    let lines = transform_table(&queue.newest_group(), delimiter, columns, transpose);
+    if !queue.replace_newest_group(lines) {
+        return Err("コピーした行がありません。".to_owned());
+    }
+    Ok(())
+}
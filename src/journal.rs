@@ -0,0 +1,256 @@
+// キュー（ClipboardData）に対する操作を追記していくジャーナル
+// 1行に1操作を記録し、起動時に先頭から再生することでキューを復元する。
+// 書き込み途中でクラッシュした場合、改行で終わっていない最後の行は無視する。
+use crate::config::RestoreQueue;
+use std::fs::{File, OpenOptions};
+use std::io::{self, Write};
+use std::path::PathBuf;
+
+// 前回の切り詰めから追記した記録数がこれを超えたら、現在のキューの内容だけを書き出して切り詰める
+const COMPACT_RECORDS: usize = 1000;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Record {
+    Add(String),
+    Commit,
+    Pop,
+    // 上限に達したため末尾（古い側）から取り除いた行数
+    Evict(usize),
+    // アンドゥ（remove_data）で削除した行数
+    Remove(usize),
+    Clear,
+    // インスペクタによる編集
+    DeleteAt(usize),
+    InsertAt(usize, String),
+    SetAt(usize, String),
+    // やり直しで末尾に戻した1回分のコピー（先頭側から順）
+    GroupBack(Vec<String>),
+    // 以下は切り詰めの際に書き出すキューの内容
+    // キューの先頭から順に1行ずつ
+    Line(String),
+    // コピー単位の行数と、未確定の行数
+    Groups(Vec<usize>, usize),
+}
+impl Record {
+    fn encode(&self) -> String {
+        match self {
+            Record::Add(s) => format!("add\t{}", escape(s)),
+            Record::Commit => "commit".to_owned(),
+            Record::Pop => "pop".to_owned(),
+            Record::Evict(n) => format!("evict\t{n}"),
+            Record::Remove(n) => format!("remove\t{n}"),
+            Record::Clear => "clear".to_owned(),
+            Record::DeleteAt(index) => format!("delete\t{index}"),
+            Record::InsertAt(index, s) => format!("insert\t{index}\t{}", escape(s)),
+            Record::SetAt(index, s) => format!("set\t{index}\t{}", escape(s)),
+            Record::GroupBack(lines) => {
+                let lines: Vec<String> = lines.iter().map(|s| escape(s)).collect();
+                format!("groupback\t{}", lines.join("\t"))
+            }
+            Record::Line(s) => format!("line\t{}", escape(s)),
+            Record::Groups(groups, pending) => {
+                let groups: Vec<String> = groups.iter().map(|n| n.to_string()).collect();
+                format!("groups\t{}\t{pending}", groups.join(","))
+            }
+        }
+    }
+    fn decode(line: &str) -> Option<Record> {
+        let mut fields = line.split('\t');
+        let record = match fields.next()? {
+            "add" => Record::Add(unescape(fields.next()?)),
+            "commit" => Record::Commit,
+            "pop" => Record::Pop,
+            "evict" => Record::Evict(fields.next()?.parse().ok()?),
+            "remove" => Record::Remove(fields.next()?.parse().ok()?),
+            "clear" => Record::Clear,
+            "delete" => Record::DeleteAt(fields.next()?.parse().ok()?),
+            "insert" => Record::InsertAt(fields.next()?.parse().ok()?, unescape(fields.next()?)),
+            "set" => Record::SetAt(fields.next()?.parse().ok()?, unescape(fields.next()?)),
+            "groupback" => Record::GroupBack(fields.map(unescape).collect()),
+            "line" => Record::Line(unescape(fields.next()?)),
+            "groups" => {
+                let groups = fields.next()?;
+                let groups = if groups.is_empty() {
+                    Vec::new()
+                } else {
+                    groups
+                        .split(',')
+                        .map(|n| n.parse().ok())
+                        .collect::<Option<Vec<usize>>>()?
+                };
+                Record::Groups(groups, fields.next()?.parse().ok()?)
+            }
+            _ => return None,
+        };
+        Some(record)
+    }
+}
+
+fn escape(s: &str) -> String {
+    let mut escaped = String::with_capacity(s.len());
+    for c in s.chars() {
+        match c {
+            '\\' => escaped.push_str("\\\\"),
+            '\n' => escaped.push_str("\\n"),
+            '\r' => escaped.push_str("\\r"),
+            '\t' => escaped.push_str("\\t"),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+fn unescape(s: &str) -> String {
+    let mut unescaped = String::with_capacity(s.len());
+    let mut chars = s.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            unescaped.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('n') => unescaped.push('\n'),
+            Some('r') => unescaped.push('\r'),
+            Some('t') => unescaped.push('\t'),
+            Some(c) => unescaped.push(c),
+            None => {}
+        }
+    }
+    unescaped
+}
+
+pub struct Journal {
+    path: PathBuf,
+    file: File,
+    // 前回の切り詰め以降に追記した記録数（切り詰めで書き出したキューの内容は数えない）
+    records: usize,
+}
+impl Journal {
+    // ジャーナルを開き、記録されている操作を返す。ファイルが無い場合は作成する。
+    pub fn open(path: &str) -> io::Result<(Journal, Vec<Record>)> {
+        let path = PathBuf::from(path);
+        let text = match std::fs::read_to_string(&path) {
+            Ok(text) => text,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => String::new(),
+            Err(e) => return Err(e),
+        };
+        let mut records = Vec::new();
+        // 最後の要素は改行で終わっていない（書き込み途中の）行なので捨てる
+        let mut lines: Vec<&str> = text.split('\n').collect();
+        lines.pop();
+        for line in lines {
+            match Record::decode(line) {
+                Some(record) => records.push(record),
+                None => println!("❌  ジャーナルの読めない行を無視しました。({line:?})"),
+            }
+        }
+        let file = OpenOptions::new().create(true).append(true).open(&path)?;
+        // LineとGroupsは切り詰めで書き出したキューの内容なので数えない
+        let appended = records
+            .iter()
+            .filter(|record| !matches!(record, Record::Line(_) | Record::Groups(..)))
+            .count();
+        let journal = Journal {
+            path,
+            file,
+            records: appended,
+        };
+        Ok((journal, records))
+    }
+    // 1操作を1回のwriteで書き込む。プロセスのクラッシュには耐えるが、電源断に備えたfsyncは切り詰め時のみ行う。
+    pub fn append(&mut self, record: &Record) {
+        let line = record.encode() + "\n";
+        if let Err(e) = self.file.write_all(line.as_bytes()) {
+            println!("❌  ジャーナルへの書き込みに失敗しました。({e})");
+            return;
+        }
+        self.records += 1;
+    }
+    pub fn should_compact(&self) -> bool {
+        self.records >= COMPACT_RECORDS
+    }
+    // 現在のキューの内容で一時ファイルを作り、ジャーナルと置き換える。
+    pub fn compact(&mut self, snapshot: &[Record]) -> io::Result<()> {
+        let mut tmp_path = self.path.clone().into_os_string();
+        tmp_path.push(".tmp");
+        let tmp_path = PathBuf::from(tmp_path);
+        let mut text = String::new();
+        for record in snapshot {
+            text.push_str(&record.encode());
+            text.push('\n');
+        }
+        {
+            let mut tmp = File::create(&tmp_path)?;
+            tmp.write_all(text.as_bytes())?;
+            tmp.sync_all()?;
+        }
+        std::fs::rename(&tmp_path, &self.path)?;
+        self.file = OpenOptions::new().append(true).open(&self.path)?;
+        self.records = 0;
+        Ok(())
+    }
+}
+
+// 前回のキューを復元するかどうか
+pub fn confirm_restore(mode: RestoreQueue, lines: usize) -> bool {
+    match mode {
+        RestoreQueue::Always => true,
+        RestoreQueue::Never => false,
+        RestoreQueue::Ask => {
+            println!("♻  前回のキューが {lines} 行残っています。復元しますか？ [Y/n]");
+            let mut answer = String::new();
+            match io::stdin().read_line(&mut answer) {
+                Ok(_) => !answer.trim().eq_ignore_ascii_case("n"),
+                Err(_) => true,
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn temp_path(name: &str) -> String {
+        let path = std::env::temp_dir().join(format!("journal-{}-{name}", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        path.to_string_lossy().into_owned()
+    }
+
+    #[test]
+    fn compacting_a_large_queue_does_not_trigger_another_compaction() {
+        let path = temp_path("large");
+        let (mut journal, _) = Journal::open(&path).unwrap();
+        let mut snapshot: Vec<Record> = (0..COMPACT_RECORDS * 2)
+            .map(|no| Record::Line(no.to_string()))
+            .collect();
+        snapshot.push(Record::Groups(vec![COMPACT_RECORDS * 2], 0));
+        journal.compact(&snapshot).unwrap();
+        assert!(!journal.should_compact());
+        journal.append(&Record::Pop);
+        assert!(!journal.should_compact());
+        // 開き直しても、切り詰めで書き出した内容は数えない
+        drop(journal);
+        let (mut journal, records) = Journal::open(&path).unwrap();
+        assert_eq!(records.len(), snapshot.len() + 1);
+        assert!(!journal.should_compact());
+        for _ in 1..COMPACT_RECORDS {
+            journal.append(&Record::Pop);
+        }
+        assert!(journal.should_compact());
+        let _ = std::fs::remove_file(&path);
+    }
+
+    #[test]
+    fn records_survive_reopening_except_a_torn_last_line() {
+        let path = temp_path("reopen");
+        let (mut journal, _) = Journal::open(&path).unwrap();
+        journal.append(&Record::Add("a\tb\\n".to_owned()));
+        journal.append(&Record::Commit);
+        drop(journal);
+        let mut file = OpenOptions::new().append(true).open(&path).unwrap();
+        file.write_all(b"add\tpartial").unwrap();
+        let (_, records) = Journal::open(&path).unwrap();
+        assert_eq!(records, [Record::Add("a\tb\\n".to_owned()), Record::Commit]);
+        let _ = std::fs::remove_file(&path);
+    }
+}
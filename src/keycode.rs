@@ -0,0 +1,214 @@
+// プラットフォームに依存しない仮想キーコードとキーストロークの定義
+// キーコードの値はWin32の仮想キーコードに合わせてある。
+// 他のプラットフォームのバックエンドはこの値との相互変換を行うこと。
+// 英数字キーは文字コード（大文字）と同じ値になる。
+pub const VK_BACK: usize = 0x08;
+pub const VK_TAB: usize = 0x09;
+pub const VK_RETURN: usize = 0x0D;
+pub const VK_ESCAPE: usize = 0x1B;
+pub const VK_SPACE: usize = 0x20;
+pub const VK_LSHIFT: usize = 0xA0;
+pub const VK_RSHIFT: usize = 0xA1;
+pub const VK_LCONTROL: usize = 0xA2;
+pub const VK_RCONTROL: usize = 0xA3;
+pub const VK_LMENU: usize = 0xA4;
+pub const VK_RMENU: usize = 0xA5;
+
+// シミュレーションのシナリオなど、人が書くキー名との対応
+// 英数字キーは1文字で、左右の区別がない名前は左側のキーとして扱う。
+const KEY_NAMES: &[(&str, usize)] = &[
+    ("BACK", VK_BACK),
+    ("TAB", VK_TAB),
+    ("RETURN", VK_RETURN),
+    ("ENTER", VK_RETURN),
+    ("ESCAPE", VK_ESCAPE),
+    ("SPACE", VK_SPACE),
+    ("LSHIFT", VK_LSHIFT),
+    ("RSHIFT", VK_RSHIFT),
+    ("SHIFT", VK_LSHIFT),
+    ("LCONTROL", VK_LCONTROL),
+    ("RCONTROL", VK_RCONTROL),
+    ("CONTROL", VK_LCONTROL),
+    ("CTRL", VK_LCONTROL),
+    ("LMENU", VK_LMENU),
+    ("RMENU", VK_RMENU),
+    ("ALT", VK_LMENU),
+];
+pub fn vk_from_name(name: &str) -> Option<usize> {
+    let name = name.trim().to_ascii_uppercase();
+    let mut chars = name.chars();
+    if let (Some(c @ ('A'..='Z' | '0'..='9')), None) = (chars.next(), chars.next()) {
+        return Some(c as usize);
+    }
+    if let Some(hex) = name.strip_prefix("0X") {
+        return usize::from_str_radix(hex, 16).ok();
+    }
+    KEY_NAMES
+        .iter()
+        .find(|(n, _)| *n == name)
+        .map(|(_, vk)| *vk)
+}
+pub fn vk_name(vk: usize) -> String {
+    match vk {
+        0x30..=0x39 | 0x41..=0x5A => (vk as u8 as char).to_string(),
+        _ => KEY_NAMES
+            .iter()
+            .find(|(_, v)| *v == vk)
+            .map(|(n, _)| n.to_string())
+            .unwrap_or_else(|| format!("0x{vk:02X}")),
+    }
+}
+
+// 仮想キーコードをスキャンコード（Set 1）に変換する。
+// OSに問い合わせられない環境向けなので、このモジュールで定義しているキーのみ対応する。
+pub fn vk_to_scancode(vk: usize) -> u16 {
+    match vk {
+        VK_BACK => 0x0E,
+        VK_TAB => 0x0F,
+        VK_RETURN => 0x1C,
+        VK_ESCAPE => 0x01,
+        VK_SPACE => 0x39,
+        VK_LSHIFT => 0x2A,
+        VK_RSHIFT => 0x36,
+        VK_LCONTROL | VK_RCONTROL => 0x1D,
+        VK_LMENU | VK_RMENU => 0x38,
+        _ => 0,
+    }
+}
+
+// KeyEvent::modifiersに設定する修飾キーのビット
+pub const MOD_SHIFT: u32 = 0x01;
+pub const MOD_CONTROL: u32 = 0x02;
+pub const MOD_ALT: u32 = 0x04;
+
+// ホストから通知されるキーイベント
+// OSのフック構造体（KBDLLHOOKSTRUCTなど）はエクスポート関数の境界でこの形式に変換する。
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct KeyEvent {
+    // 仮想キーコード（このモジュールの定義に従う）
+    pub vk: u32,
+    pub scan_code: u32,
+    // 自分自身が送信したキーストロークであればtrue。trueのイベントは無視される。
+    pub injected: bool,
+    // イベントの発生時刻（ミリ秒）
+    pub time: u64,
+    // 修飾キーを個別のイベントとして通知できないホストは、押されている修飾キーをここで指定する。
+    // 個別に通知している場合は0で良い。
+    pub modifiers: u32,
+}
+impl KeyEvent {
+    pub fn new(vk: usize) -> Self {
+        KeyEvent {
+            vk: vk as u32,
+            scan_code: vk_to_scancode(vk) as u32,
+            ..Default::default()
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum KeyAction {
+    // 押して離す
+    Press,
+    Down,
+    Up,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum KeyStroke {
+    Key(usize, KeyAction),
+    Char(char),
+}
+
+// 送信するキーストロークの列
+// バックエンドはこれを実際のキー入力に変換して送信する。
+// 機密の行の文字を含むことがあるので、クリアした時と破棄した時に文字を上書きする。
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct KeySequence {
+    delay_msec: u64,
+    // 機密の行の文字を含む場合はtrue（記録や表示をする際に伏せる）
+    sensitive: bool,
+    strokes: Vec<KeyStroke>,
+}
+impl KeySequence {
+    pub fn new() -> Self {
+        KeySequence::default()
+    }
+    // キーストローク間の待機時間
+    pub fn set_delay(&mut self, delay_msec: u64) {
+        self.delay_msec = delay_msec;
+    }
+    pub fn get_delay(&self) -> u64 {
+        self.delay_msec
+    }
+    pub fn set_sensitive(&mut self, sensitive: bool) {
+        self.sensitive = sensitive;
+    }
+    pub fn is_sensitive(&self) -> bool {
+        self.sensitive
+    }
+    pub fn push_key(&mut self, vk: usize, action: KeyAction) {
+        self.reserve(1);
+        self.strokes.push(KeyStroke::Key(vk, action));
+    }
+    pub fn push_str(&mut self, s: &str) {
+        self.reserve(s.chars().count());
+        for c in s.chars() {
+            self.strokes.push(KeyStroke::Char(c));
+        }
+    }
+    pub fn clear(&mut self) {
+        for stroke in self.strokes.iter_mut() {
+            // 最適化で書き込みが省略されないようにする
+            unsafe { std::ptr::write_volatile(stroke, KeyStroke::Char('\0')) };
+        }
+        std::sync::atomic::compiler_fence(std::sync::atomic::Ordering::SeqCst);
+        self.strokes.clear();
+    }
+    // Vecに任せて再確保すると古い領域に文字が残るので、足りない場合は自分で確保し直して古い領域を上書きする
+    fn reserve(&mut self, additional: usize) {
+        if self.strokes.capacity() - self.strokes.len() >= additional {
+            return;
+        }
+        let capacity = (self.strokes.len() + additional).max(self.strokes.capacity() * 2);
+        let mut strokes = Vec::with_capacity(capacity);
+        strokes.extend_from_slice(&self.strokes);
+        self.clear();
+        self.strokes = strokes;
+    }
+    pub fn strokes(&self) -> &[KeyStroke] {
+        &self.strokes
+    }
+}
+impl Drop for KeySequence {
+    fn drop(&mut self) {
+        self.clear();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn strokes_survive_growing_the_sequence() {
+        let mut kbd = KeySequence::new();
+        kbd.push_key(VK_LCONTROL, KeyAction::Up);
+        kbd.push_str("ab");
+        kbd.push_str("\n");
+        kbd.push_key(VK_LCONTROL, KeyAction::Down);
+        let chars: String = kbd
+            .strokes()
+            .iter()
+            .filter_map(|stroke| match stroke {
+                KeyStroke::Char(c) => Some(*c),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(chars, "ab\n");
+        assert_eq!(kbd.strokes().len(), 5);
+        kbd.clear();
+        assert!(kbd.strokes().is_empty());
+    }
+}
@@ -1,3 +1,18 @@
 pub mod entry;
+mod clipboard;
 mod config;
-mod default;
\ No newline at end of file
+mod default;
+mod filter;
+mod hook;
+mod importer;
+mod injector;
+mod inspector;
+mod journal;
+pub mod keycode;
+mod platform;
+mod queue;
+pub mod simulate;
+mod secret;
+mod snapshot;
+mod splitter;
+mod window;
@@ -0,0 +1,169 @@
+// Linuxの入力イベント（evdev）のキーコードとの相互変換
+// evdevのキーコードはlinux/input-event-codes.hの値をそのまま使用する。
+use crate::config::KeyboardLayout;
+use crate::keycode::*;
+
+// 自分自身が作成した仮想デバイスを区別できるよう、仮想デバイスの名前にはこの接頭辞を付ける。
+// Windowsでいうところの LLKHF_INJECTED + dwExtraInfo に相当する。
+pub const VIRTUAL_DEVICE_NAME_PREFIX: &str = "multiline_paster";
+pub const VIRTUAL_DEVICE_NAME: &str = "multiline_paster virtual keyboard";
+
+pub fn is_self_injected_device(device_name: Option<&str>) -> bool {
+    device_name
+        .map(|name| name.starts_with(VIRTUAL_DEVICE_NAME_PREFIX))
+        .unwrap_or(false)
+}
+
+const KEY_ESC: u16 = 1;
+const KEY_BACKSPACE: u16 = 14;
+const KEY_TAB: u16 = 15;
+const KEY_ENTER: u16 = 28;
+const KEY_LEFTCTRL: u16 = 29;
+const KEY_LEFTSHIFT: u16 = 42;
+const KEY_RIGHTSHIFT: u16 = 54;
+const KEY_LEFTALT: u16 = 56;
+const KEY_SPACE: u16 = 57;
+const KEY_RO: u16 = 89;
+const KEY_RIGHTCTRL: u16 = 97;
+const KEY_RIGHTALT: u16 = 100;
+const KEY_YEN: u16 = 124;
+
+// QWERTY配列のアルファベットキー（A-Z順）
+const ALPHABET_KEYS: [u16; 26] = [
+    30, 48, 46, 32, 18, 33, 34, 35, 23, 36, 37, 38, 50, 49, 24, 25, 16, 19, 31, 20, 22, 47, 17, 45,
+    21, 44,
+];
+// 数字キー（0-9順）
+const DIGIT_KEYS: [u16; 10] = [11, 2, 3, 4, 5, 6, 7, 8, 9, 10];
+
+// 記号と(キーコード, SHIFTが必要か)の対応
+const US_SYMBOLS: &[(char, u16, bool)] = &[
+    ('-', 12, false),
+    ('=', 13, false),
+    ('[', 26, false),
+    (']', 27, false),
+    (';', 39, false),
+    ('\'', 40, false),
+    ('`', 41, false),
+    ('\\', 43, false),
+    (',', 51, false),
+    ('.', 52, false),
+    ('/', 53, false),
+    ('!', 2, true),
+    ('@', 3, true),
+    ('#', 4, true),
+    ('$', 5, true),
+    ('%', 6, true),
+    ('^', 7, true),
+    ('&', 8, true),
+    ('*', 9, true),
+    ('(', 10, true),
+    (')', 11, true),
+    ('_', 12, true),
+    ('+', 13, true),
+    ('{', 26, true),
+    ('}', 27, true),
+    (':', 39, true),
+    ('"', 40, true),
+    ('~', 41, true),
+    ('|', 43, true),
+    ('<', 51, true),
+    ('>', 52, true),
+    ('?', 53, true),
+];
+const JP_SYMBOLS: &[(char, u16, bool)] = &[
+    ('-', 12, false),
+    ('^', 13, false),
+    ('@', 26, false),
+    ('[', 27, false),
+    (';', 39, false),
+    (':', 40, false),
+    (']', 43, false),
+    (',', 51, false),
+    ('.', 52, false),
+    ('/', 53, false),
+    ('\\', KEY_RO, false),
+    ('!', 2, true),
+    ('"', 3, true),
+    ('#', 4, true),
+    ('$', 5, true),
+    ('%', 6, true),
+    ('&', 7, true),
+    ('\'', 8, true),
+    ('(', 9, true),
+    (')', 10, true),
+    ('=', 12, true),
+    ('~', 13, true),
+    ('`', 26, true),
+    ('{', 27, true),
+    ('+', 39, true),
+    ('*', 40, true),
+    ('}', 43, true),
+    ('<', 51, true),
+    ('>', 52, true),
+    ('?', 53, true),
+    ('_', KEY_RO, true),
+    ('|', KEY_YEN, true),
+];
+
+pub const SHIFT_KEY: u16 = KEY_LEFTSHIFT;
+
+// 文字を入力するためのキーを返す。(キーコード, SHIFTが必要か)
+// 配列上に存在しない文字はNone
+pub fn char_to_evdev(c: char, layout: KeyboardLayout) -> Option<(u16, bool)> {
+    match c {
+        'a'..='z' => Some((ALPHABET_KEYS[c as usize - 'a' as usize], false)),
+        'A'..='Z' => Some((ALPHABET_KEYS[c as usize - 'A' as usize], true)),
+        '0'..='9' => Some((DIGIT_KEYS[c as usize - '0' as usize], false)),
+        ' ' => Some((KEY_SPACE, false)),
+        '\t' => Some((KEY_TAB, false)),
+        '\n' => Some((KEY_ENTER, false)),
+        _ => {
+            let symbols = match layout {
+                KeyboardLayout::Us => US_SYMBOLS,
+                KeyboardLayout::Jp => JP_SYMBOLS,
+            };
+            symbols
+                .iter()
+                .find(|(sym, _, _)| *sym == c)
+                .map(|(_, code, shift)| (*code, *shift))
+        }
+    }
+}
+
+pub fn vk_to_evdev(vk: usize) -> Option<u16> {
+    evdev_to_vk_table()
+        .iter()
+        .find(|(_, v)| *v == vk)
+        .map(|(code, _)| *code)
+}
+
+pub fn evdev_to_vk(code: u16) -> Option<usize> {
+    evdev_to_vk_table()
+        .iter()
+        .find(|(c, _)| *c == code)
+        .map(|(_, vk)| *vk)
+}
+
+fn evdev_to_vk_table() -> Vec<(u16, usize)> {
+    let mut table = vec![
+        (KEY_ESC, VK_ESCAPE),
+        (KEY_BACKSPACE, VK_BACK),
+        (KEY_TAB, VK_TAB),
+        (KEY_ENTER, VK_RETURN),
+        (KEY_SPACE, VK_SPACE),
+        (KEY_LEFTCTRL, VK_LCONTROL),
+        (KEY_RIGHTCTRL, VK_RCONTROL),
+        (KEY_LEFTSHIFT, VK_LSHIFT),
+        (KEY_RIGHTSHIFT, VK_RSHIFT),
+        (KEY_LEFTALT, VK_LMENU),
+        (KEY_RIGHTALT, VK_RMENU),
+    ];
+    for (i, code) in ALPHABET_KEYS.iter().enumerate() {
+        table.push((*code, 'A' as usize + i));
+    }
+    for (i, code) in DIGIT_KEYS.iter().enumerate() {
+        table.push((*code, '0' as usize + i));
+    }
+    table
+}
@@ -0,0 +1,184 @@
+use super::evdev_keymap::*;
+use crate::hook::HookController;
+use crate::keycode::KeyEvent;
+use evdev::{
+    uinput::{VirtualDevice, VirtualDeviceBuilder},
+    Device, InputEvent, InputEventKind, Key,
+};
+use multiline_parser_pluginlib::result::PluginResult;
+use std::time::UNIX_EPOCH;
+
+// 再送用の仮想デバイスの名前（自分自身のデバイスとして無視されるよう接頭辞を揃える）
+const PASSTHROUGH_DEVICE_NAME: &str = "multiline_paster passthrough keyboard";
+
+// /dev/input 以下のキーボードを監視し、キーイベントをkey_down/key_upに流し込む。
+// grabが有効な場合はデバイスを占有し、コンボキーとして処理されなかったイベントだけを仮想デバイスから再送する。
+// これによって PluginResult::NoChain と同じようにキー入力を握りつぶせる。
+// 監視を始めたキーボードの数と、そのうち占有できた数を返す。
+pub fn spawn_keyboard_listener(grab: bool) -> (usize, usize) {
+    let mut listen_count = 0;
+    let mut grab_count = 0;
+    for (path, mut device) in evdev::enumerate() {
+        if is_self_injected_device(device.name()) || !is_keyboard(&device) {
+            continue;
+        }
+        println!(
+            "⌨  キーボード {} ({}) を監視します。",
+            device.name().unwrap_or_default(),
+            path.display()
+        );
+        let passthrough = if grab { grab_device(&mut device) } else { None };
+        if passthrough.is_some() {
+            grab_count += 1;
+        }
+        std::thread::spawn(move || listen(device, passthrough));
+        listen_count += 1;
+    }
+    (listen_count, grab_count)
+}
+
+fn is_keyboard(device: &Device) -> bool {
+    device
+        .supported_keys()
+        .map(|keys| keys.contains(Key::KEY_A) && keys.contains(Key::KEY_LEFTCTRL))
+        .unwrap_or(false)
+}
+
+// キーボードを占有し、握りつぶさなかったイベントを再送する仮想デバイスを返す
+fn grab_device(device: &mut Device) -> Option<VirtualDevice> {
+    let passthrough = device.supported_keys().map(|keys| {
+        VirtualDeviceBuilder::new()
+            .and_then(|builder| builder.name(PASSTHROUGH_DEVICE_NAME).with_keys(keys))
+            .and_then(|builder| builder.build())
+    });
+    match (passthrough, device.grab()) {
+        (Some(Ok(passthrough)), Ok(())) => Some(passthrough),
+        (Some(Err(e)), _) | (_, Err(e)) => {
+            println!("❌  キーボードを占有できませんでした。コンボキーは握りつぶされません。({e})");
+            let _ = device.ungrab();
+            None
+        }
+        (None, Ok(())) => {
+            let _ = device.ungrab();
+            None
+        }
+    }
+}
+
+fn listen(mut device: Device, mut passthrough: Option<VirtualDevice>) {
+    let mut v_suppressed = false;
+    loop {
+        let events = match device.fetch_events() {
+            Ok(events) => events.collect::<Vec<InputEvent>>(),
+            Err(e) => {
+                println!("❌  キーボードの監視を終了します。({e})");
+                break;
+            }
+        };
+        let mut forward = Vec::new();
+        for event in events {
+            match event.kind() {
+                InputEventKind::Synchronization(_) => continue,
+                InputEventKind::Key(_) if !handle_key(&event, &mut v_suppressed) => continue,
+                _ => forward.push(InputEvent::new(
+                    event.event_type(),
+                    event.code(),
+                    event.value(),
+                )),
+            }
+        }
+        if let Some(passthrough) = passthrough.as_mut() {
+            if !forward.is_empty() {
+                if let Err(e) = passthrough.emit(&forward) {
+                    println!("❌  キーイベントを再送できませんでした。({e})");
+                }
+            }
+        }
+    }
+}
+
+// キーイベントを処理する。後続（アプリケーション）に渡してよい場合はtrue
+// value: 0=離した 1=押した 2=キーリピート
+// Ctrl+Vでペーストを開始した場合は、OSが貼り付けないようにVキーを渡さない。
+// 非同期のペースト処理を待たずに判定できるよう、握りつぶしたかどうかはv_suppressedに記録して、リピートと離した時にも使う。
+fn handle_key(event: &InputEvent, v_suppressed: &mut bool) -> bool {
+    let vk = match evdev_to_vk(event.code()) {
+        Some(vk) => vk,
+        None => return true,
+    };
+    let is_v = vk == 'V' as usize;
+    // ペーストはキーリピートで繰り返さない
+    if is_v && event.value() == 2 {
+        return !*v_suppressed;
+    }
+    let key_event = KeyEvent {
+        vk: vk as u32,
+        scan_code: event.code() as u32,
+        injected: false,
+        time: event
+            .timestamp()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_millis() as u64)
+            .unwrap_or_default(),
+        modifiers: 0,
+    };
+    if event.value() == 0 {
+        let result = crate::default::key_up(0, &key_event);
+        if is_v && std::mem::take(v_suppressed) {
+            return false;
+        }
+        return !matches!(result, PluginResult::NoChain);
+    }
+    let (result, paste_started) = crate::default::handle_key_down(&key_event);
+    if is_v {
+        *v_suppressed = paste_started;
+    }
+    !paste_started && !matches!(result, PluginResult::NoChain)
+}
+
+// 占有したキーボードからのCtrl+Vを再送しないことで、OSの貼り付けを抑止する
+// 抑止するかどうかはhandle_keyがキーイベントごとに判定するので、ここでは状態を持たない。
+// キーボードを1つも占有できなかった場合は使用しないこと。
+pub struct EvdevHookController;
+impl HookController for EvdevHookController {
+    fn set_hook(&mut self) -> Result<(), String> {
+        Ok(())
+    }
+    fn can_suppress_ctrl_v(&self) -> bool {
+        true
+    }
+    fn suppress_ctrl_v(&mut self) {}
+    fn release_ctrl_v(&mut self) {}
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::{Config, InputMode};
+    use crate::default::tests::setup;
+    use evdev::EventType;
+
+    fn key(key: Key, value: i32) -> InputEvent {
+        InputEvent::new(EventType::KEY, key.code(), value)
+    }
+
+    #[test]
+    fn v_is_not_forwarded_while_ctrl_v_pastes() {
+        let engine = setup(Config::default(), InputMode::Clipboard);
+        engine.copy("first\nsecond");
+        let mut v_suppressed = false;
+        assert!(handle_key(&key(Key::KEY_LEFTCTRL, 1), &mut v_suppressed));
+        assert!(!handle_key(&key(Key::KEY_V, 1), &mut v_suppressed));
+        // ペースト処理がCtrl+Vの抑止を解除した後のリピートと離した時も渡さない
+        while engine.writes().is_empty() {
+            std::thread::yield_now();
+        }
+        assert!(!handle_key(&key(Key::KEY_V, 2), &mut v_suppressed));
+        assert!(!handle_key(&key(Key::KEY_V, 0), &mut v_suppressed));
+        assert!(handle_key(&key(Key::KEY_LEFTCTRL, 0), &mut v_suppressed));
+        assert_eq!(engine.writes(), ["first"]);
+        // ペーストしていない時のVはそのまま渡す
+        assert!(handle_key(&key(Key::KEY_V, 1), &mut v_suppressed));
+        assert!(handle_key(&key(Key::KEY_V, 0), &mut v_suppressed));
+    }
+}
@@ -0,0 +1,69 @@
+// Windows以外のバックエンド
+// 既定ではOSの機能を一切使用せず、クリップボードはメモリ上に保持し、キー入力は送信しない。
+// x11/uinput/evdevフィーチャが有効な場合はconfigureでそれぞれのバックエンドに差し替える。
+// 記録用のバックエンド（RecordingInjectorなど）はシミュレーターとテストで明示的に設定する。
+use crate::clipboard::{ClipboardBackend, MemoryClipboard};
+use crate::config::Config;
+use crate::hook::{DegradedHookController, HookController};
+use crate::injector::{KeyInjector, NullInjector};
+use crate::window::{MockWindowInfoProvider, WindowInfoProvider};
+
+pub fn default_clipboard_backend() -> Box<dyn ClipboardBackend> {
+    Box::new(MemoryClipboard::new())
+}
+
+// 設定に従ってバックエンドを差し替える
+pub fn configure(_config: &Config) {
+    #[cfg(not(feature = "x11"))]
+    println!("🔴  クリップボードのバックエンド（x11フィーチャ）が有効ではありません。コピーした内容はこのプロセスのメモリ上にのみ保持されます。");
+    #[cfg(not(feature = "uinput"))]
+    println!("🔴  キー入力のバックエンド（uinputフィーチャ）が有効ではありません。キーボードエミュレーションによるペーストは行われません。");
+    #[cfg(feature = "x11")]
+    match super::x11::X11Clipboard::new(_config.capture_selection) {
+        Ok(cb) => {
+            let cache = cb.cache();
+            crate::clipboard::set_clipboard_backend(Box::new(cb));
+            if let Err(e) = super::x11::spawn_selection_watcher(_config.capture_selection, cache) {
+                println!("❌  セレクションの監視を開始できませんでした。({e})");
+            }
+        }
+        Err(e) => println!("❌  X11のクリップボードに接続できませんでした。({e})"),
+    }
+    #[cfg(feature = "x11")]
+    match super::x11::X11WindowInfoProvider::new() {
+        Ok(provider) => crate::window::set_window_info_provider(Box::new(provider)),
+        Err(e) => println!("❌  X11のアクティブウィンドウを取得できません。({e})"),
+    }
+    #[cfg(feature = "uinput")]
+    match super::uinput::UinputKeyInjector::new(_config.keyboard_layout) {
+        Ok(injector) => crate::injector::set_key_injector(Box::new(injector)),
+        Err(e) => println!("❌  /dev/uinput を開けませんでした。キー入力は送信されません。({e})"),
+    }
+    #[cfg(feature = "evdev")]
+    {
+        let (listen_count, grab_count) =
+            super::evdev_listener::spawn_keyboard_listener(_config.grab_keyboard);
+        if listen_count == 0 {
+            println!("❌  監視できるキーボードがありません。/dev/input の権限を確認してください。");
+        }
+        // Ctrl+Vを握りつぶせるのはキーボードを占有できた場合のみ
+        if grab_count > 0 {
+            crate::hook::set_hook_controller(Box::new(super::evdev_listener::EvdevHookController));
+        }
+    }
+}
+
+pub fn default_key_injector() -> Box<dyn KeyInjector> {
+    Box::new(NullInjector)
+}
+
+pub fn default_window_info_provider() -> Box<dyn WindowInfoProvider> {
+    Box::new(MockWindowInfoProvider::new())
+}
+
+// evdevでキーボードを占有できた場合はconfigureで差し替える
+pub fn default_hook_controller() -> Box<dyn HookController> {
+    Box::new(DegradedHookController::new(
+        "Ctrl+Vを抑止できるキーボードフックがありません".to_owned(),
+    ))
+}
@@ -0,0 +1,23 @@
+// OSに依存する処理はここに集約する。
+// win32フィーチャが無効な場合はheadless（とLinux向けの各フィーチャ）のバックエンドが使用される。
+#[cfg(all(feature = "win32", feature = "x11"))]
+compile_error!("x11フィーチャはwin32フィーチャと同時には使用できません。--no-default-features --features x11 としてください。");
+
+#[cfg(feature = "win32")]
+mod win32;
+#[cfg(feature = "win32")]
+pub use win32::*;
+
+#[cfg(not(feature = "win32"))]
+mod headless;
+#[cfg(not(feature = "win32"))]
+pub use headless::*;
+
+#[cfg(any(feature = "uinput", feature = "evdev"))]
+mod evdev_keymap;
+#[cfg(feature = "evdev")]
+mod evdev_listener;
+#[cfg(feature = "uinput")]
+pub mod uinput;
+#[cfg(feature = "x11")]
+pub mod x11;
@@ -0,0 +1,76 @@
+use super::evdev_keymap::*;
+use crate::config::KeyboardLayout;
+use crate::injector::KeyInjector;
+use crate::keycode::*;
+use evdev::{
+    uinput::{VirtualDevice, VirtualDeviceBuilder},
+    AttributeSet, EventType, InputEvent, Key,
+};
+use std::time::Duration;
+
+// /dev/uinput の仮想キーボードによるキーストロークの送信
+pub struct UinputKeyInjector {
+    device: VirtualDevice,
+    layout: KeyboardLayout,
+}
+impl UinputKeyInjector {
+    pub fn new(layout: KeyboardLayout) -> std::io::Result<Self> {
+        let mut keys = AttributeSet::<Key>::new();
+        for code in 1..256 {
+            keys.insert(Key::new(code));
+        }
+        let device = VirtualDeviceBuilder::new()?
+            .name(VIRTUAL_DEVICE_NAME)
+            .with_keys(&keys)?
+            .build()?;
+        Ok(UinputKeyInjector { device, layout })
+    }
+    fn emit_key(&mut self, code: u16, value: i32) {
+        if let Err(e) = self
+            .device
+            .emit(&[InputEvent::new(EventType::KEY, code, value)])
+        {
+            println!("❌  キーストロークを送信できませんでした。({e})");
+        }
+    }
+}
+impl KeyInjector for UinputKeyInjector {
+    fn send(&mut self, seq: &KeySequence) {
+        for (i, stroke) in seq.strokes().iter().enumerate() {
+            if i != 0 && seq.get_delay() != 0 {
+                std::thread::sleep(Duration::from_millis(seq.get_delay()));
+            }
+            match stroke {
+                KeyStroke::Key(vk, action) => {
+                    let code = match vk_to_evdev(*vk) {
+                        Some(code) => code,
+                        None => continue,
+                    };
+                    if *action != KeyAction::Up {
+                        self.emit_key(code, 1);
+                    }
+                    if *action != KeyAction::Down {
+                        self.emit_key(code, 0);
+                    }
+                }
+                KeyStroke::Char(c) => match char_to_evdev(*c, self.layout) {
+                    Some((code, shift)) => {
+                        if shift {
+                            self.emit_key(SHIFT_KEY, 1);
+                        }
+                        self.emit_key(code, 1);
+                        self.emit_key(code, 0);
+                        if shift {
+                            self.emit_key(SHIFT_KEY, 0);
+                        }
+                    }
+                    // CRLFの場合は\nのみでEnterを送信する
+                    None if *c == '\r' => {}
+                    None => {
+                        println!("❌  文字 {c:?} は現在のキーボード配列では入力できません。");
+                    }
+                },
+            }
+        }
+    }
+}
@@ -0,0 +1,357 @@
+use crate::clipboard::ClipboardBackend;
+use crate::config::Config;
+use crate::hook::{DegradedHookController, HookController};
+use crate::injector::KeyInjector;
+use crate::keycode::{KeyAction, KeyEvent, KeySequence, KeyStroke};
+use crate::window::{WindowInfo, WindowInfoProvider};
+use send_input::keyboard::windows::*;
+use std::ffi::OsString;
+use std::os::windows::ffi::{OsStrExt, OsStringExt};
+use windows::core::{PCWSTR, PWSTR};
+use zeroize::{Zeroize, Zeroizing};
+use windows::Win32::{
+    Foundation::*,
+    System::{DataExchange::*, Memory::*, SystemServices::*, Threading::*, WindowsProgramming::*},
+    UI::{Input::KeyboardAndMouse::*, WindowsAndMessaging::*},
+};
+
+// Win32のクリップボード
+pub struct Win32Clipboard {}
+impl ClipboardBackend for Win32Clipboard {
+    fn open(&mut self) -> bool {
+        unsafe { OpenClipboard(HWND::default()).as_bool() }
+    }
+    fn close(&mut self) {
+        unsafe {
+            CloseClipboard();
+        }
+    }
+    fn get_text(&mut self) -> Option<String> {
+        unsafe {
+            let h_text = GetClipboardData(CF_UNICODETEXT.0).ok()?;
+            // クリップボードにデータがあったらロックする
+            let p_text = GlobalLock(h_text.0);
+            let text = u16_ptr_to_text(p_text as *const _);
+            GlobalUnlock(h_text.0);
+            text
+        }
+    }
+    fn set_text(&mut self, text: &str) {
+        // 機密の行の内容が残らないよう、必要な長さを先に確保し、書き込んだらゼロクリアする
+        let mut data = Zeroizing::new(Vec::with_capacity(text.len() + 1));
+        data.extend(text.encode_utf16());
+        data.push(0);
+        let strdata_len = data.len() * 2;
+        let data_ptr = data.as_ptr();
+        unsafe {
+            let gdata = GlobalAlloc(GHND | GLOBAL_ALLOC_FLAGS(GMEM_SHARE), strdata_len);
+            let locked_data = GlobalLock(gdata) as *mut u8;
+            if locked_data.is_null() {
+                GlobalFree(gdata);
+                return;
+            }
+            std::ptr::copy_nonoverlapping(data_ptr as *const u8, locked_data, strdata_len);
+            GlobalUnlock(gdata);
+            // SAFETY: 書き込めた場合、ハンドルはクリップボードが所有するので開放しない。
+            // 書き込めなかった場合は、内容をゼロクリアしてから開放する。
+            if SetClipboardData(CF_UNICODETEXT.0, HANDLE(gdata)).is_err() {
+                let locked_data = GlobalLock(gdata) as *mut u8;
+                if !locked_data.is_null() {
+                    std::slice::from_raw_parts_mut(locked_data, strdata_len).zeroize();
+                    GlobalUnlock(gdata);
+                }
+                GlobalFree(gdata);
+            }
+        }
+    }
+    // 機密として指定する形式も書き込む（値はCanIncludeInClipboardHistoryとCanUploadToCloudClipboardの0だけが意味を持つ）
+    fn set_sensitive_text(&mut self, text: &str) {
+        self.set_text(text);
+        for name in [
+            "ExcludeClipboardContentFromMonitorProcessing",
+            "CanIncludeInClipboardHistory",
+            "CanUploadToCloudClipboard",
+        ] {
+            set_clipboard_dword(name, 0);
+        }
+    }
+    fn clear(&mut self) {
+        unsafe {
+            EmptyClipboard();
+        }
+    }
+    fn sequence_number(&self) -> u32 {
+        unsafe { GetClipboardSequenceNumber() }
+    }
+    // ExcludeClipboardContentFromMonitorProcessing があるか、CanIncludeInClipboardHistory が0の場合は機密とみなす
+    fn is_sensitive(&mut self) -> bool {
+        unsafe {
+            let exclude = register_clipboard_format("ExcludeClipboardContentFromMonitorProcessing");
+            if exclude != 0 && IsClipboardFormatAvailable(exclude).as_bool() {
+                return true;
+            }
+            let history = register_clipboard_format("CanIncludeInClipboardHistory");
+            if history == 0 || !IsClipboardFormatAvailable(history).as_bool() {
+                return false;
+            }
+            let h_data = match GetClipboardData(history) {
+                Ok(h_data) => h_data,
+                Err(_) => return false,
+            };
+            let p_data = GlobalLock(h_data.0) as *const u32;
+            let can_include = p_data.is_null() || *p_data != 0;
+            GlobalUnlock(h_data.0);
+            !can_include
+        }
+    }
+}
+
+// 登録した形式でDWORDの値をクリップボードに書き込む
+fn set_clipboard_dword(name: &str, value: u32) {
+    let format = register_clipboard_format(name);
+    if format == 0 {
+        return;
+    }
+    unsafe {
+        let gdata = GlobalAlloc(
+            GHND | GLOBAL_ALLOC_FLAGS(GMEM_SHARE),
+            std::mem::size_of::<u32>(),
+        );
+        let locked_data = GlobalLock(gdata) as *mut u32;
+        if locked_data.is_null() {
+            GlobalFree(gdata);
+            return;
+        }
+        *locked_data = value;
+        GlobalUnlock(gdata);
+        // 書き込めた場合、メモリはシステムが管理するので開放しない
+        if SetClipboardData(format, HANDLE(gdata)).is_err() {
+            GlobalFree(gdata);
+        }
+    }
+}
+
+fn register_clipboard_format(name: &str) -> u32 {
+    let mut name = OsString::from(name).encode_wide().collect::<Vec<u16>>();
+    name.push(0);
+    unsafe { RegisterClipboardFormatW(PCWSTR(name.as_ptr())) }
+}
+
+pub fn default_clipboard_backend() -> Box<dyn ClipboardBackend> {
+    Box::new(Win32Clipboard {})
+}
+
+pub fn configure(_config: &Config) {}
+
+// SendInputによるキーストロークの送信
+pub struct Win32KeyInjector {}
+impl KeyInjector for Win32KeyInjector {
+    fn send(&mut self, seq: &KeySequence) {
+        send_keys(seq);
+    }
+}
+
+pub fn default_key_injector() -> Box<dyn KeyInjector> {
+    Box::new(Win32KeyInjector {})
+}
+
+fn send_keys(seq: &KeySequence) {
+    let mut kbd = Keyboard::new();
+    kbd.new_delay(seq.get_delay());
+    for stroke in seq.strokes() {
+        match stroke {
+            KeyStroke::Key(vk, action) => {
+                let vk = VIRTUAL_KEY(*vk as u16);
+                let keycode = match action {
+                    KeyAction::Press => KeycodeBuilder::default()
+                        .vk(vk.0)
+                        .scan_code(virtual_key_to_scancode(vk))
+                        .build(),
+                    KeyAction::Down => KeycodeBuilder::default()
+                        .vk(vk.0)
+                        .scan_code(virtual_key_to_scancode(vk))
+                        .key_send_mode(KeySendMode::KeyDown)
+                        .build(),
+                    KeyAction::Up => KeycodeBuilder::default()
+                        .vk(vk.0)
+                        .scan_code(virtual_key_to_scancode(vk))
+                        .key_send_mode(KeySendMode::KeyUp)
+                        .build(),
+                };
+                kbd.append_input_chain(keycode);
+            }
+            KeyStroke::Char(c) => {
+                KeycodeBuilder::default()
+                    .char_build(*c)
+                    .iter()
+                    .for_each(|keycode| kbd.append_input_chain(keycode.clone()));
+            }
+        }
+    }
+    kbd.send_key();
+}
+
+// 自分自身（SendInput）が送信したキーストロークか判定する
+fn is_self_injected(stroke_msg: &KBDLLHOOKSTRUCT) -> bool {
+    stroke_msg.flags.0 & (LLKHF_INJECTED.0 | LLKHF_LOWER_IL_INJECTED.0) != 0
+        && stroke_msg.dwExtraInfo != 0
+}
+
+// Windowsのホストは修飾キーも個別に通知するので、modifiersは設定しない。
+pub fn to_key_event(stroke_msg: &KBDLLHOOKSTRUCT) -> KeyEvent {
+    KeyEvent {
+        vk: stroke_msg.vkCode,
+        scan_code: stroke_msg.scanCode,
+        injected: is_self_injected(stroke_msg),
+        time: stroke_msg.time as u64,
+        modifiers: 0,
+    }
+}
+
+pub struct Win32WindowInfoProvider {}
+impl WindowInfoProvider for Win32WindowInfoProvider {
+    fn active_window(&mut self) -> Option<WindowInfo> {
+        let active_window = unsafe { GetForegroundWindow() };
+        if active_window.0 == 0 {
+            return None;
+        }
+        let mut pid = 0u32;
+        let mut class = vec![0u16; 256];
+        let class_len = unsafe {
+            GetWindowThreadProcessId(active_window, Some(&mut pid as *mut u32));
+            GetClassNameW(active_window, &mut class)
+        };
+        Some(WindowInfo {
+            title: get_window_text(active_window),
+            process_name: get_process_name(pid).unwrap_or_default(),
+            pid,
+            class: String::from_utf16_lossy(&class[0..class_len.max(0) as usize]),
+        })
+    }
+}
+
+pub fn default_window_info_provider() -> Box<dyn WindowInfoProvider> {
+    Box::new(Win32WindowInfoProvider {})
+}
+
+fn get_process_name(pid: u32) -> Option<String> {
+    unsafe {
+        let process = OpenProcess(PROCESS_QUERY_LIMITED_INFORMATION, false, pid).ok()?;
+        let mut buf = vec![0u16; MAX_PATH as usize];
+        let mut len = buf.len() as u32;
+        let result = QueryFullProcessImageNameW(
+            process,
+            PROCESS_NAME_WIN32,
+            PWSTR(buf.as_mut_ptr()),
+            &mut len,
+        );
+        CloseHandle(process);
+        if !result.as_bool() {
+            return None;
+        }
+        // フルパスからファイル名だけを取り出す
+        let path = OsString::from_wide(&buf[0..len as usize]);
+        std::path::Path::new(&path)
+            .file_name()
+            .map(|name| name.to_string_lossy().into_owned())
+    }
+}
+
+type DllCtrlNoticeApi = unsafe extern "C" fn() -> bool;
+type DllSetHookApi = unsafe extern "C" fn() -> bool;
+// inputctl.dll によるフックとCtrl+Vの抑止
+pub struct DllHookController {
+    _dll: libloading::Library,
+    sethook: DllSetHookApi,
+    notice_ctrl_v: DllCtrlNoticeApi,
+    ignore_ctrl_v: DllCtrlNoticeApi,
+}
+impl DllHookController {
+    pub fn load() -> Result<Self, libloading::Error> {
+        unsafe {
+            let dll = libloading::Library::new("inputctl.dll")?;
+            let sethook = *dll.get::<DllSetHookApi>(b"sethook")?;
+            let notice_ctrl_v = *dll.get::<DllCtrlNoticeApi>(b"notice_ctrl_v")?;
+            let ignore_ctrl_v = *dll.get::<DllCtrlNoticeApi>(b"ignore_ctrl_v")?;
+            Ok(DllHookController {
+                _dll: dll,
+                sethook,
+                notice_ctrl_v,
+                ignore_ctrl_v,
+            })
+        }
+    }
+}
+impl HookController for DllHookController {
+    fn set_hook(&mut self) -> Result<(), String> {
+        if unsafe { (self.sethook)() } {
+            Ok(())
+        } else {
+            Err("inputctl.dll の sethook が失敗しました".to_owned())
+        }
+    }
+    fn can_suppress_ctrl_v(&self) -> bool {
+        true
+    }
+    fn suppress_ctrl_v(&mut self) {
+        unsafe { (self.ignore_ctrl_v)() };
+    }
+    fn release_ctrl_v(&mut self) {
+        unsafe { (self.notice_ctrl_v)() };
+    }
+}
+
+pub fn default_hook_controller() -> Box<dyn HookController> {
+    match DllHookController::load() {
+        Ok(controller) => Box::new(controller),
+        Err(e) => {
+            println!("🔴  ライブラリ inputctl.dll が読み込めませんでした。({e})");
+            Box::new(DegradedHookController::new(
+                "inputctl.dll が読み込めません".to_owned(),
+            ))
+        }
+    }
+}
+
+fn virtual_key_to_scancode(vk: VIRTUAL_KEY) -> u16 {
+    unsafe { MapVirtualKeyA(vk.0 as u32, MAPVK_VK_TO_VSC as u32) as u16 }
+}
+
+fn get_window_text(hwnd: HWND) -> String {
+    unsafe {
+        // GetWindowTextLengthW + GetWindowTextWは別プロセスへの取得を意図したものではないとの記述がMSDNにあるので
+        // SendMessageWで取得することにする。
+        let len = SendMessageW(hwnd, WM_GETTEXTLENGTH, WPARAM(0), LPARAM(0)).0 as usize + 1;
+        let mut buf = vec![0u16; len];
+        SendMessageW(
+            hwnd,
+            WM_GETTEXT,
+            WPARAM(len),
+            LPARAM(buf.as_mut_ptr() as isize),
+        );
+        OsString::from_wide(&buf[0..buf.len() - 1])
+            .to_os_string()
+            .into_string()
+            .unwrap()
+    }
+}
+
+// クリップボードのUTF-16の文字列を、途中の領域を作らずにStringにする（不正な文字列の場合はNone）
+unsafe fn u16_ptr_to_text(ptr: *const u16) -> Option<String> {
+    let len = (0..).take_while(|&i| *ptr.offset(i) != 0).count();
+    let units = std::slice::from_raw_parts(ptr, len);
+    let capacity = char::decode_utf16(units.iter().copied())
+        .map(|c| c.map_or(0, char::len_utf8))
+        .sum();
+    let mut text = String::with_capacity(capacity);
+    for c in char::decode_utf16(units.iter().copied()) {
+        match c {
+            Ok(c) => text.push(c),
+            Err(_) => {
+                text.zeroize();
+                return None;
+            }
+        }
+    }
+    Some(text)
+}
@@ -0,0 +1,363 @@
+use crate::clipboard::ClipboardBackend;
+use crate::config::CaptureSelection;
+use crate::window::{WindowInfo, WindowInfoProvider};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use x11_clipboard::{error::Error, Atom, Clipboard};
+use x11rb::connection::Connection;
+use x11rb::protocol::xproto::{
+    AtomEnum, ConnectionExt, CreateWindowAux, EventMask, GetPropertyReply, PropMode,
+    SelectionNotifyEvent, Window, WindowClass, SELECTION_NOTIFY_EVENT,
+};
+use x11rb::protocol::Event;
+use x11rb::wrapper::ConnectionExt as _;
+use x11rb::rust_connection::RustConnection;
+use zeroize::{Zeroize, Zeroizing};
+
+// セレクションの所有者が応答しない場合に諦めるまでの時間
+const LOAD_TIMEOUT_MSEC: u64 = 300;
+
+// セレクションの監視スレッドが読み込んだ内容
+// X11のセレクションにはシーケンス番号が無いので、内容が前回と異なれば変更されたとみなして番号を増やす。
+#[derive(Default)]
+pub struct SelectionCache {
+    // パスワードマネージャーがコピーした内容なども含むので、置き換えた時にゼロクリアする
+    text: Option<Zeroizing<String>>,
+    sequence: u32,
+    // 監視スレッドが動いている間だけ内容が最新であることを保証できる
+    watching: bool,
+}
+impl SelectionCache {
+    fn update(&mut self, text: Option<String>) {
+        let text = text.map(Zeroizing::new);
+        if text != self.text {
+            self.text = text;
+            self.sequence = self.sequence.wrapping_add(1);
+        }
+    }
+}
+
+// X11のCLIPBOARD/PRIMARYセレクション
+// 読み込みは設定されたセレクションから行い、書き込みはCtrl+Vで貼り付けられるよう常にCLIPBOARDに行う。
+// セレクションの読み込みは所有者との往復で時間がかかるので、監視スレッドが読み込んだ内容を使用する。
+pub struct X11Clipboard {
+    clipboard: Clipboard,
+    capture: CaptureSelection,
+    cache: Arc<Mutex<SelectionCache>>,
+}
+impl X11Clipboard {
+    pub fn new(capture: CaptureSelection) -> Result<Self, Error> {
+        Ok(X11Clipboard {
+            clipboard: Clipboard::new()?,
+            capture,
+            cache: Arc::new(Mutex::new(SelectionCache::default())),
+        })
+    }
+    // spawn_selection_watcherに渡す
+    pub fn cache(&self) -> Arc<Mutex<SelectionCache>> {
+        self.cache.clone()
+    }
+    fn store(&mut self, text: &str) {
+        let atoms = &self.clipboard.getter.atoms;
+        if let Err(e) = self
+            .clipboard
+            .store(atoms.clipboard, atoms.utf8_string, text)
+        {
+            println!("❌  CLIPBOARDセレクションへの書き込みに失敗しました。({e})");
+            return;
+        }
+        self.stored(text);
+    }
+    // 書き込んだ内容は監視スレッドを待たずに反映しておく
+    fn stored(&mut self, text: &str) {
+        let mut cache = self.cache.lock().unwrap();
+        if self.capture == CaptureSelection::Clipboard {
+            cache.text = Some(Zeroizing::new(text.to_owned()));
+        }
+        cache.sequence = cache.sequence.wrapping_add(1);
+    }
+}
+impl ClipboardBackend for X11Clipboard {
+    fn open(&mut self) -> bool {
+        // 監視スレッドが動いていない場合は内容が古い可能性があるので、その場で読み込む
+        let watching = self.cache.lock().unwrap().watching;
+        if !watching {
+            let text = load_text(&self.clipboard, self.capture);
+            self.cache.lock().unwrap().update(text);
+        }
+        true
+    }
+    fn close(&mut self) {}
+    fn get_text(&mut self) -> Option<String> {
+        self.cache.lock().unwrap().text.as_deref().cloned()
+    }
+    fn set_text(&mut self, text: &str) {
+        self.store(text);
+    }
+    // x-kde-passwordManagerHint に "secret" を設定して、クリップボードマネージャーが記録しないようにする
+    fn set_sensitive_text(&mut self, text: &str) {
+        if let Err(e) = spawn_secret_owner(text) {
+            println!("❌  CLIPBOARDセレクションへの書き込みに失敗しました。({e})");
+            return;
+        }
+        self.stored(text);
+    }
+    fn clear(&mut self) {
+        self.store("");
+    }
+    fn sequence_number(&self) -> u32 {
+        self.cache.lock().unwrap().sequence
+    }
+    // KeePassXCなどは x-kde-passwordManagerHint ターゲットに "secret" を設定する
+    fn is_sensitive(&mut self) -> bool {
+        let hint = match self.clipboard.getter.get_atom("x-kde-passwordManagerHint") {
+            Ok(hint) => hint,
+            Err(_) => return false,
+        };
+        let atoms = &self.clipboard.getter.atoms;
+        self.clipboard
+            .load(
+                selection_atom(&self.clipboard, self.capture),
+                hint,
+                atoms.property,
+                Duration::from_millis(LOAD_TIMEOUT_MSEC),
+            )
+            .map_or(false, |data| data == b"secret")
+    }
+}
+
+fn selection_atom(clipboard: &Clipboard, selection: CaptureSelection) -> Atom {
+    match selection {
+        CaptureSelection::Clipboard => clipboard.getter.atoms.clipboard,
+        CaptureSelection::Primary => clipboard.getter.atoms.primary,
+    }
+}
+
+fn load_text(clipboard: &Clipboard, selection: CaptureSelection) -> Option<String> {
+    let atoms = &clipboard.getter.atoms;
+    let data = clipboard
+        .load(
+            selection_atom(clipboard, selection),
+            atoms.utf8_string,
+            atoms.property,
+            Duration::from_millis(LOAD_TIMEOUT_MSEC),
+        )
+        .ok()?;
+    match String::from_utf8(data) {
+        Ok(text) => Some(text),
+        Err(e) => {
+            e.into_bytes().zeroize();
+            None
+        }
+    }
+}
+
+// 機密の内容をCLIPBOARDセレクションに設定する。
+// x11-clipboardは1つのセレクションに1つのターゲットしか提供できないので、UTF8_STRINGと
+// x-kde-passwordManagerHintを提供する所有者のスレッドを起動する。スレッドは別の所有者に変わると終了し、内容をゼロクリアする。
+// 機密の内容は短いので、INCRによる分割転送には対応しない。
+fn spawn_secret_owner(text: &str) -> Result<(), Box<dyn std::error::Error>> {
+    let (connection, screen) = RustConnection::connect(None)?;
+    let root = connection.setup().roots[screen].root;
+    let window = connection.generate_id()?;
+    connection.create_window(
+        x11rb::COPY_DEPTH_FROM_PARENT,
+        window,
+        root,
+        0,
+        0,
+        1,
+        1,
+        0,
+        WindowClass::INPUT_OUTPUT,
+        x11rb::COPY_FROM_PARENT,
+        &CreateWindowAux::new(),
+    )?;
+    let intern = |name: &[u8]| -> Result<Atom, Box<dyn std::error::Error>> {
+        Ok(connection.intern_atom(false, name)?.reply()?.atom)
+    };
+    let clipboard = intern(b"CLIPBOARD")?;
+    let targets = intern(b"TARGETS")?;
+    let utf8_string = intern(b"UTF8_STRING")?;
+    let hint = intern(b"x-kde-passwordManagerHint")?;
+    connection.set_selection_owner(window, clipboard, x11rb::CURRENT_TIME)?;
+    if connection.get_selection_owner(clipboard)?.reply()?.owner != window {
+        return Err("CLIPBOARDセレクションの所有者になれませんでした".into());
+    }
+    let text = Zeroizing::new(text.as_bytes().to_vec());
+    std::thread::spawn(move || {
+        while let Ok(event) = connection.wait_for_event() {
+            let request = match event {
+                Event::SelectionRequest(request) => request,
+                Event::SelectionClear(_) => break,
+                _ => continue,
+            };
+            // 古いクライアントはpropertyにNoneを指定するので、その場合はターゲットに書き込む
+            let property = match request.property {
+                x11rb::NONE => request.target,
+                property => property,
+            };
+            let written = if request.target == targets {
+                connection
+                    .change_property32(
+                        PropMode::REPLACE,
+                        request.requestor,
+                        property,
+                        AtomEnum::ATOM,
+                        &[targets, utf8_string, hint],
+                    )
+                    .is_ok()
+            } else if request.target == utf8_string || request.target == hint {
+                let data: &[u8] = if request.target == hint { b"secret" } else { &text };
+                connection
+                    .change_property8(
+                        PropMode::REPLACE,
+                        request.requestor,
+                        property,
+                        request.target,
+                        data,
+                    )
+                    .is_ok()
+            } else {
+                false
+            };
+            let notify = SelectionNotifyEvent {
+                response_type: SELECTION_NOTIFY_EVENT,
+                sequence: 0,
+                time: request.time,
+                requestor: request.requestor,
+                selection: request.selection,
+                target: request.target,
+                property: if written { property } else { x11rb::NONE },
+            };
+            let _ = connection.send_event(false, request.requestor, EventMask::NO_EVENT, notify);
+            let _ = connection.flush();
+        }
+        let _ = connection.destroy_window(window);
+        let _ = connection.flush();
+    });
+    Ok(())
+}
+
+// セレクションの変更を監視してコピー処理に通知するスレッドを起動する。
+// 読み込んだ内容はcacheに保存してから通知するので、コピー処理ではセレクションを読み直さない。
+// CLIPBOARDの場合はCtrl+C/Xが押されていた時のみコピーし、PRIMARYの場合は選択しただけでコピーする。
+pub fn spawn_selection_watcher(
+    capture: CaptureSelection,
+    cache: Arc<Mutex<SelectionCache>>,
+) -> Result<(), Error> {
+    let watcher = Clipboard::new()?;
+    std::thread::spawn(move || {
+        let atoms = watcher.getter.atoms.clone();
+        let selection = selection_atom(&watcher, capture);
+        // 監視を始める前からある内容を読み込んでおく
+        let text = load_text(&watcher, capture);
+        {
+            let mut cache = cache.lock().unwrap();
+            cache.update(text);
+            cache.watching = true;
+        }
+        loop {
+            match watcher.load_wait(selection, atoms.utf8_string, atoms.property) {
+                Ok(data) => {
+                    cache.lock().unwrap().update(String::from_utf8(data).ok());
+                    match capture {
+                        CaptureSelection::Clipboard => crate::default::update_clipboard(),
+                        CaptureSelection::Primary => crate::default::copy_selection(),
+                    }
+                }
+                Err(e) => {
+                    cache.lock().unwrap().watching = false;
+                    println!("❌  セレクションの監視を終了します。({e})");
+                    break;
+                }
+            }
+        }
+    });
+    Ok(())
+}
+
+// _NET_ACTIVE_WINDOWなどのEWMHプロパティからアクティブウィンドウの情報を取得する
+pub struct X11WindowInfoProvider {
+    connection: RustConnection,
+    root: Window,
+    atoms: WindowAtoms,
+}
+struct WindowAtoms {
+    net_active_window: Atom,
+    net_wm_name: Atom,
+    net_wm_pid: Atom,
+    utf8_string: Atom,
+}
+impl X11WindowInfoProvider {
+    pub fn new() -> Result<Self, Box<dyn std::error::Error>> {
+        let (connection, screen) = RustConnection::connect(None)?;
+        let root = connection.setup().roots[screen].root;
+        let intern = |name: &[u8]| -> Result<Atom, Box<dyn std::error::Error>> {
+            Ok(connection.intern_atom(false, name)?.reply()?.atom)
+        };
+        let atoms = WindowAtoms {
+            net_active_window: intern(b"_NET_ACTIVE_WINDOW")?,
+            net_wm_name: intern(b"_NET_WM_NAME")?,
+            net_wm_pid: intern(b"_NET_WM_PID")?,
+            utf8_string: intern(b"UTF8_STRING")?,
+        };
+        Ok(X11WindowInfoProvider {
+            connection,
+            root,
+            atoms,
+        })
+    }
+    fn property(&self, window: Window, property: Atom, type_: Atom) -> Option<GetPropertyReply> {
+        self.connection
+            .get_property(false, window, property, type_, 0, u32::MAX / 4)
+            .ok()?
+            .reply()
+            .ok()
+    }
+    fn property_u32(&self, window: Window, property: Atom, type_: Atom) -> Option<u32> {
+        self.property(window, property, type_)?.value32()?.next()
+    }
+    fn property_string(&self, window: Window, property: Atom, type_: Atom) -> Option<String> {
+        let reply = self.property(window, property, type_)?;
+        if reply.value.is_empty() {
+            return None;
+        }
+        Some(String::from_utf8_lossy(&reply.value).into_owned())
+    }
+}
+impl WindowInfoProvider for X11WindowInfoProvider {
+    fn active_window(&mut self) -> Option<WindowInfo> {
+        let window = self.property_u32(
+            self.root,
+            self.atoms.net_active_window,
+            AtomEnum::WINDOW.into(),
+        )?;
+        if window == x11rb::NONE {
+            return None;
+        }
+        let title = self
+            .property_string(window, self.atoms.net_wm_name, self.atoms.utf8_string)
+            .or_else(|| {
+                self.property_string(window, AtomEnum::WM_NAME.into(), AtomEnum::STRING.into())
+            })
+            .unwrap_or_default();
+        // WM_CLASSは "インスタンス名\0クラス名\0" の形式
+        let class = self
+            .property_string(window, AtomEnum::WM_CLASS.into(), AtomEnum::STRING.into())
+            .and_then(|class| class.split('\0').nth(1).map(|s| s.to_owned()))
+            .unwrap_or_default();
+        let pid = self
+            .property_u32(window, self.atoms.net_wm_pid, AtomEnum::CARDINAL.into())
+            .unwrap_or(0);
+        let process_name = std::fs::read_to_string(format!("/proc/{pid}/comm"))
+            .map(|name| name.trim_end().to_owned())
+            .unwrap_or_default();
+        Some(WindowInfo {
+            title,
+            process_name,
+            pid,
+            class,
+        })
+    }
+}
@@ -0,0 +1,892 @@
+use crate::config::{QueueOrder, RestoreQueue};
+use crate::journal::{Journal, Record};
+use crate::secret;
+use crate::splitter::split_line_ending;
+use serde_derive::{Deserialize, Serialize};
+use std::collections::{HashMap, VecDeque};
+
+// 名前を指定しなかった場合に使用するレジスタ
+pub const DEFAULT_REGISTER: &str = "default";
+
+// 名前付きのキュー（レジスタ）
+// 存在しないレジスタは最初に使用した時に作成する。
+pub struct Registers {
+    queues: HashMap<String, ClipboardData>,
+    // ジャーナルを保存する場合のパス（デフォルトレジスタのもの）
+    journal_path: Option<String>,
+    restore: RestoreQueue,
+}
+impl Registers {
+    pub fn new() -> Self {
+        Registers {
+            queues: HashMap::new(),
+            journal_path: None,
+            restore: RestoreQueue::default(),
+        }
+    }
+    pub fn get(&mut self, name: &str) -> &mut ClipboardData {
+        if !self.queues.contains_key(name) {
+            let mut queue = ClipboardData::new();
+            // 起動後に追加されたレジスタは、問い合わせずに前回の内容を復元してから記録を続ける
+            // （restore_queue = "never" の場合のみ空の状態から始める）
+            if let Some(path) = &self.journal_path {
+                match Journal::open(&register_journal_path(path, name)) {
+                    Ok((journal, records)) => {
+                        if self.restore != RestoreQueue::Never {
+                            queue.restore(records);
+                        }
+                        queue.attach_journal(journal);
+                        // 機密の行の内容はプロセスの終了とともに破棄されている
+                        queue.remove_matching(secret::is_token);
+                        let lines = queue.get_clipboard_lines();
+                        if lines > 0 {
+                            println!("♻  レジスタ「{name}」に前回のキューを {lines} 行復元しました。");
+                        }
+                    }
+                    Err(e) => {
+                        println!("❌  レジスタ「{name}」のジャーナルを開けませんでした。({e})")
+                    }
+                }
+            }
+            self.queues.insert(name.to_owned(), queue);
+        }
+        self.queues.get_mut(name).unwrap()
+    }
+    pub fn iter_mut(&mut self) -> impl Iterator<Item = &mut ClipboardData> {
+        self.queues.values_mut()
+    }
+    pub fn contains(&self, line: &str) -> bool {
+        self.queues.values().any(|queue| queue.contains(line))
+    }
+    pub fn set_journal_path(&mut self, path: &str, restore: RestoreQueue) {
+        self.journal_path = Some(path.to_owned());
+        self.restore = restore;
+    }
+    pub fn insert(&mut self, name: &str, queue: ClipboardData) {
+        self.queues.insert(name.to_owned(), queue);
+    }
+}
+
+// デフォルトレジスタは指定されたパスに、それ以外はレジスタ名を付けたパスに保存する。
+pub fn register_journal_path(path: &str, name: &str) -> String {
+    if name == DEFAULT_REGISTER {
+        path.to_owned()
+    } else {
+        format!("{path}.{name}")
+    }
+}
+
+// キュー全体に対する一括操作
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum QueueOperation {
+    Reverse,
+    Sort,
+    Shuffle,
+}
+
+// シャッフル用の簡易な乱数
+struct XorShift(u64);
+impl XorShift {
+    fn new() -> Self {
+        let seed = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .map(|d| d.as_nanos() as u64)
+            .unwrap_or_default();
+        XorShift(seed | 1)
+    }
+    fn next(&mut self) -> u64 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        self.0
+    }
+}
+
+// 上限に達した場合の動作
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum OnLimit {
+    // コピーした行をすべて取り込まない
+    #[default]
+    Refuse,
+    // 収まる分だけ取り込み、長すぎる行は切り詰める
+    Truncate,
+    // 古い行から取り除いて、コピーした行を取り込む（長すぎる行は切り詰める）
+    Evict,
+}
+
+// キューの上限。0の場合は制限しない
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Default)]
+#[serde(default)]
+pub struct QueueLimit {
+    pub max_entries: usize,
+    pub max_bytes: usize,
+    // コピーした1行の文字数（改行を除く）
+    pub max_entry_length: usize,
+    pub on_limit: OnLimit,
+}
+
+#[derive(Debug, Default)]
+pub struct LimitReport {
+    pub added: usize,
+    // 文字数の上限で切り詰めた行
+    pub truncated: usize,
+    // キューに収まらなかったため取り込まなかった行
+    pub dropped: usize,
+    // 取り除いた古い行
+    pub evicted: usize,
+}
+
+// やり直しのために保存しておく操作の数
+const REDO_HISTORY: usize = 32;
+
+// アンドゥやクリアで削除した行
+// groupsは削除した行をコピー単位ごとに区切った行数（古い順）
+enum Removed {
+    // 先頭から削除した行（先頭側から順）
+    Front {
+        lines: Vec<String>,
+        groups: Vec<usize>,
+    },
+    Clear {
+        lines: Vec<String>,
+        groups: Vec<usize>,
+    },
+}
+
+pub struct ClipboardData {
+    data: VecDeque<String>,
+    copied_lines: Vec<usize>,
+    add_line_count: usize,
+    journal: Option<Journal>,
+    redo: VecDeque<Removed>,
+    // スティッキーモードでペーストする行の位置（末尾からの距離、0が次にペーストされる行）
+    cursor: usize,
+}
+impl ClipboardData {
+    pub fn new() -> Self {
+        ClipboardData {
+            data: VecDeque::new(),
+            copied_lines: Vec::new(),
+            add_line_count: 0,
+            journal: None,
+            redo: VecDeque::new(),
+            cursor: 0,
+        }
+    }
+    // 以降の操作をジャーナルに記録する。ジャーナルは現在の内容で切り詰められる。
+    pub fn attach_journal(&mut self, mut journal: Journal) {
+        if let Err(e) = journal.compact(&self.snapshot()) {
+            println!("❌  ジャーナルを切り詰められませんでした。({e})");
+        }
+        self.journal = Some(journal);
+    }
+    // ジャーナルに記録された操作を再生する
+    pub fn restore(&mut self, records: Vec<Record>) {
+        let journal = self.journal.take();
+        for record in records {
+            match record {
+                Record::Add(s) => self.add_clipboard(s),
+                Record::Commit => self.commit_copy_lines(),
+                Record::Pop => {
+                    self.pop_back();
+                }
+                Record::Evict(n) => self.drop_back(n),
+                Record::Remove(n) => {
+                    self.remove_data(n);
+                }
+                Record::Clear => self.clipboard_clear(),
+                Record::DeleteAt(index) => {
+                    self.delete_at(index);
+                }
+                Record::InsertAt(index, s) => self.insert_at(index, s),
+                Record::SetAt(index, s) => {
+                    self.set_at(index, s);
+                }
+                Record::GroupBack(lines) => self.push_group_back(lines),
+                Record::Line(s) => self.data.push_back(s),
+                Record::Groups(groups, pending) => {
+                    self.copied_lines = groups;
+                    self.add_line_count = pending;
+                }
+            }
+        }
+        // やり直しの履歴は保存していないので、再生中に積まれたものは捨てる
+        self.redo.clear();
+        self.journal = journal;
+    }
+    fn snapshot(&self) -> Vec<Record> {
+        let mut records: Vec<Record> = self.data.iter().cloned().map(Record::Line).collect();
+        records.push(Record::Groups(
+            self.copied_lines.clone(),
+            self.add_line_count,
+        ));
+        records
+    }
+    fn write_journal(&mut self, record: Record) {
+        let mut journal = match self.journal.take() {
+            Some(journal) => journal,
+            None => return,
+        };
+        journal.append(&record);
+        // キューが空になった時か、記録が溜まった時に切り詰める
+        if journal.should_compact() || self.data.is_empty() {
+            if let Err(e) = journal.compact(&self.snapshot()) {
+                println!("❌  ジャーナルを切り詰められませんでした。({e})");
+            }
+        }
+        self.journal = Some(journal);
+    }
+    pub fn pop_back(&mut self) -> Option<String> {
+        if let Some(size) = self.group_size_at(self.data.len().wrapping_sub(1)) {
+            *size -= 1;
+        }
+        self.copied_lines.retain(|size| *size != 0);
+        let data = self.data.pop_back();
+        if data.is_some() {
+            // カーソルが指している行は動かさない
+            self.cursor = self.cursor.saturating_sub(1);
+            self.write_journal(Record::Pop);
+        }
+        data
+    }
+    // 末尾（次にペーストされる側）からcount行をまとめて取り除く
+    fn drop_back(&mut self, count: usize) {
+        let count = count.min(self.data.len());
+        if count == 0 {
+            return;
+        }
+        // 末尾側から、どのコピー単位にも属さない行、古いコピー単位、未確定の行の順に並んでいる
+        let grouped = self.add_line_count + self.copied_lines.iter().sum::<usize>();
+        let mut rest = count.saturating_sub(self.data.len().saturating_sub(grouped));
+        let whole = self
+            .copied_lines
+            .iter()
+            .take_while(|size| {
+                let whole = **size <= rest;
+                if whole {
+                    rest -= **size;
+                }
+                whole
+            })
+            .count();
+        self.copied_lines.drain(..whole);
+        match self.copied_lines.first_mut() {
+            Some(size) => *size -= rest,
+            None => self.add_line_count -= rest,
+        }
+        self.data.truncate(self.data.len() - count);
+        // カーソルが指している行は動かさない
+        self.cursor = self.cursor.saturating_sub(count);
+        self.write_journal(Record::Evict(count));
+    }
+    // 並び順に従って次にペーストされる行の位置
+    fn next_index(&self, order: QueueOrder) -> Option<usize> {
+        match order {
+            QueueOrder::Fifo => self.data.len().checked_sub(1),
+            QueueOrder::Lifo => (!self.data.is_empty()).then_some(0),
+            QueueOrder::GroupReversed => {
+                // 一番古いコピー単位のうち、最後にコピーされた行
+                let entries = self.entries();
+                let (oldest, _) = entries.last()?;
+                entries.iter().position(|(group, _)| group == oldest)
+            }
+        }
+    }
+    // 並び順に従って次にペーストする行を取り出す
+    pub fn take(&mut self, order: QueueOrder) -> Option<String> {
+        match order {
+            QueueOrder::Fifo => self.pop_back(),
+            order => {
+                let index = self.next_index(order)?;
+                self.delete_at(index)
+            }
+        }
+    }
+    // 指定した行を、次にペーストされる位置（スティッキーモードではカーソル位置）に移動する
+    pub fn move_to_next(&mut self, index: usize, order: QueueOrder, sticky_mode: bool) -> bool {
+        let data = match self.delete_at(index) {
+            Some(data) => data,
+            None => return false,
+        };
+        let len = self.data.len();
+        let to = if sticky_mode {
+            // 挿入後に末尾からカーソル番目の位置になるようにする
+            len - self.cursor.min(len)
+        } else {
+            match order {
+                // 末尾に挿入すると、一番古いコピー単位の最初の行になる
+                QueueOrder::Fifo => len,
+                order => self.next_index(order).unwrap_or(0),
+            }
+        };
+        self.insert_at(to, data);
+        true
+    }
+    // コピー単位ごとに行を並べ替える。コピー単位の区切りは変わらないので、アンドゥは並べ替え後の行に対して行われる。
+    pub fn reorder_groups(&mut self, operation: QueueOperation) {
+        let mut sizes: Vec<usize> = vec![self.add_line_count];
+        sizes.extend(self.copied_lines.iter().rev());
+        let mut rest: Vec<String> = self.data.drain(..).collect();
+        let mut random = XorShift::new();
+        for size in sizes {
+            let take = size.min(rest.len());
+            let mut group: Vec<String> = rest.drain(..take).collect();
+            match operation {
+                QueueOperation::Reverse => group.reverse(),
+                QueueOperation::Sort => {
+                    // ペーストされる順（末尾から）で昇順になるようにする
+                    group.sort();
+                    group.reverse();
+                }
+                QueueOperation::Shuffle => {
+                    for i in (1..group.len()).rev() {
+                        group.swap(i, random.next() as usize % (i + 1));
+                    }
+                }
+            }
+            self.data.extend(group);
+        }
+        // どのコピー単位にも属さない行はそのまま
+        self.data.extend(rest);
+        self.rewrite_journal();
+    }
+    // 重複した行を取り除く。先にペーストされる行を残す。取り除いた行数を返す。
+    pub fn unique(&mut self) -> usize {
+        let mut seen = std::collections::HashSet::new();
+        let mut duplicates = Vec::new();
+        for (index, line) in self.data.iter().enumerate().rev() {
+            if !seen.insert(line.clone()) {
+                duplicates.push(index);
+            }
+        }
+        // 末尾側から取り除くので、残りの行の位置はずれない
+        let journal = self.journal.take();
+        for index in &duplicates {
+            self.delete_at(*index);
+        }
+        self.journal = journal;
+        self.rewrite_journal();
+        duplicates.len()
+    }
+    // キューかやり直しの履歴にその行があるか
+    pub fn contains(&self, line: &str) -> bool {
+        self.data.iter().any(|data| data == line)
+            || self.redo.iter().any(|removed| match removed {
+                Removed::Front { lines, .. } | Removed::Clear { lines, .. } => {
+                    lines.iter().any(|data| data == line)
+                }
+            })
+    }
+    // 条件に一致する行を取り除き、取り除いた行数を返す
+    pub fn remove_matching(&mut self, pred: impl Fn(&str) -> bool) -> usize {
+        let indexes: Vec<usize> = (0..self.data.len())
+            .rev()
+            .filter(|index| pred(&self.data[*index]))
+            .collect();
+        for index in &indexes {
+            self.delete_at(*index);
+        }
+        indexes.len()
+    }
+    pub fn byte_len(&self) -> usize {
+        self.data.iter().map(|line| line.len()).sum()
+    }
+    // コピーした行を上限に従って1回のコピーとして取り込む。取り込みを拒否した場合は理由を返す。
+    pub fn add_copy(
+        &mut self,
+        lines: Vec<String>,
+        limit: &QueueLimit,
+    ) -> Result<LimitReport, String> {
+        let mut report = LimitReport::default();
+        let mut lines = lines;
+        if limit.max_entry_length != 0 {
+            // 機密の行はトークンなので切り詰めない
+            let too_long = |line: &String| {
+                !secret::is_token(line)
+                    && split_line_ending(line).0.chars().count() > limit.max_entry_length
+            };
+            let count = lines.iter().filter(|line| too_long(line)).count();
+            if count > 0 && limit.on_limit == OnLimit::Refuse {
+                return Err(format!(
+                    "{}文字を超える行が{count}行あります",
+                    limit.max_entry_length
+                ));
+            }
+            for line in lines.iter_mut().filter(|line| too_long(line)) {
+                let (body, ending) = split_line_ending(line);
+                *line = body
+                    .chars()
+                    .take(limit.max_entry_length)
+                    .collect::<String>()
+                    + ending;
+            }
+            report.truncated = count;
+        }
+        let mut entries = self.data.len();
+        let mut bytes = self.byte_len();
+        let fits = |entries: usize, bytes: usize| {
+            (limit.max_entries == 0 || entries <= limit.max_entries)
+                && (limit.max_bytes == 0 || bytes <= limit.max_bytes)
+        };
+        match limit.on_limit {
+            OnLimit::Refuse => {
+                let added_bytes: usize = lines.iter().map(|line| line.len()).sum();
+                if !fits(entries + lines.len(), bytes + added_bytes) {
+                    return Err(format!(
+                        "キューの上限を超えます（{}行 / {}バイト）",
+                        entries + lines.len(),
+                        bytes + added_bytes
+                    ));
+                }
+            }
+            OnLimit::Truncate => {
+                // 先頭から収まる分だけ取り込む
+                let mut count = 0;
+                for line in &lines {
+                    if !fits(entries + 1, bytes + line.len()) {
+                        break;
+                    }
+                    entries += 1;
+                    bytes += line.len();
+                    count += 1;
+                }
+                report.dropped = lines.len() - count;
+                lines.truncate(count);
+            }
+            OnLimit::Evict => {
+                // ペーストされる順（古い行、コピーした行の順）に、上限に収まるまでに取り除く行数を数える
+                let mut count = 0;
+                entries += lines.len();
+                bytes += lines.iter().map(|line| line.len()).sum::<usize>();
+                for line in self.data.iter().rev().chain(lines.iter()) {
+                    if fits(entries, bytes) {
+                        break;
+                    }
+                    entries -= 1;
+                    bytes -= line.len();
+                    count += 1;
+                }
+                // drop_backはコピー単位の行数も更新するので、アンドゥの対象がずれることはない
+                report.evicted = count.min(self.data.len());
+                self.drop_back(report.evicted);
+                // 古い行をすべて取り除いても収まらない場合は、コピーした行の最初の方を取り込まない
+                report.dropped = count - report.evicted;
+                lines.drain(..report.dropped);
+            }
+        }
+        report.added = lines.len();
+        for line in lines {
+            self.add_clipboard(line);
+        }
+        // すべて除外された場合は、空のコピー単位を作るとアンドゥが何もしなくなるので確定しない
+        if report.added > 0 {
+            self.commit_copy_lines();
+        }
+        Ok(report)
+    }
+    // 最後にコピーした単位の行を、コピーした順（上から下）で返す
+    pub fn newest_group(&self) -> Vec<String> {
+        let size = self.copied_lines.last().copied().unwrap_or(0);
+        let start = self.add_line_count.min(self.data.len());
+        let end = (start + size).min(self.data.len());
+        self.data.range(start..end).rev().cloned().collect()
+    }
+    // 最後にコピーした単位の行を置き換える。行数が変わってもアンドゥでまとめて取り消せる。
+    pub fn replace_newest_group(&mut self, lines: Vec<String>) -> bool {
+        let size = match self.copied_lines.last() {
+            Some(size) => *size,
+            None => return false,
+        };
+        let start = self.add_line_count.min(self.data.len());
+        let end = (start + size).min(self.data.len());
+        self.data.drain(start..end);
+        for (i, line) in lines.iter().rev().enumerate() {
+            self.data.insert(start + i, line.clone());
+        }
+        self.copied_lines.pop();
+        if !lines.is_empty() {
+            self.copied_lines.push(lines.len());
+        }
+        self.rewrite_journal();
+        true
+    }
+    // 行を一括で並べ替えた場合は、操作を記録する代わりに現在の内容でジャーナルを書き直す
+    fn rewrite_journal(&mut self) {
+        let snapshot = self.snapshot();
+        if let Some(journal) = self.journal.as_mut() {
+            if let Err(e) = journal.compact(&snapshot) {
+                println!("❌  ジャーナルを書き直せませんでした。({e})");
+            }
+        }
+    }
+    // 行の削除などでカーソルが範囲外になった場合は先頭の行を指す
+    pub fn get_cursor(&self) -> usize {
+        self.cursor.min(self.data.len().saturating_sub(1))
+    }
+    pub fn line_at_cursor(&self) -> Option<String> {
+        let len = self.data.len();
+        if len == 0 {
+            return None;
+        }
+        self.data.get(len - 1 - self.get_cursor()).cloned()
+    }
+    // カーソル位置から先頭までの行数
+    pub fn lines_from_cursor(&self) -> usize {
+        if self.data.is_empty() {
+            0
+        } else {
+            self.data.len() - self.get_cursor()
+        }
+    }
+    // forwardがtrueなら次にコピーされた行へ、falseなら前の行へ移動する
+    pub fn move_cursor(&mut self, forward: bool) -> usize {
+        let cursor = self.get_cursor();
+        self.cursor = if forward {
+            (cursor + 1).min(self.data.len().saturating_sub(1))
+        } else {
+            cursor.saturating_sub(1)
+        };
+        self.cursor
+    }
+    pub fn commit_copy_lines(&mut self) {
+        self.copied_lines.push(self.add_line_count);
+        self.add_line_count = 0;
+        self.write_journal(Record::Commit);
+    }
+    pub fn add_clipboard(&mut self, data: String) {
+        self.data.push_front(data);
+        self.add_line_count += 1;
+        if self.journal.is_some() {
+            self.write_journal(Record::Add(self.data[0].clone()));
+        }
+    }
+    // 先頭から順に、各行が属するコピー単位の番号（copied_linesの添字）を返す。未確定の行と、どこにも属さない行はNone
+    pub fn entries(&self) -> Vec<(Option<usize>, &String)> {
+        let mut groups = vec![None; self.add_line_count.min(self.data.len())];
+        for (no, size) in self.copied_lines.iter().enumerate().rev() {
+            groups.extend(std::iter::repeat(Some(no)).take(*size));
+        }
+        groups.resize(self.data.len(), None);
+        groups.into_iter().zip(self.data.iter()).collect()
+    }
+    // 指定した位置の行を含むコピー単位の行数
+    fn group_size_at(&mut self, index: usize) -> Option<&mut usize> {
+        let mut end = self.add_line_count;
+        if index < end {
+            return Some(&mut self.add_line_count);
+        }
+        for size in self.copied_lines.iter_mut().rev() {
+            end += *size;
+            if index < end {
+                return Some(size);
+            }
+        }
+        None
+    }
+    pub fn delete_at(&mut self, index: usize) -> Option<String> {
+        if index >= self.data.len() {
+            return None;
+        }
+        if let Some(size) = self.group_size_at(index) {
+            *size -= 1;
+        }
+        self.copied_lines.retain(|size| *size != 0);
+        let data = self.data.remove(index);
+        self.write_journal(Record::DeleteAt(index));
+        data
+    }
+    // 挿入した行は、挿入位置にある行と同じコピー単位に含める
+    pub fn insert_at(&mut self, index: usize, data: String) {
+        let index = index.min(self.data.len());
+        if let Some(size) = self.group_size_at(index.min(self.data.len().saturating_sub(1))) {
+            *size += 1;
+        }
+        self.data.insert(index, data.clone());
+        self.write_journal(Record::InsertAt(index, data));
+    }
+    pub fn set_at(&mut self, index: usize, data: String) -> bool {
+        match self.data.get_mut(index) {
+            Some(line) => *line = data.clone(),
+            None => return false,
+        }
+        self.write_journal(Record::SetAt(index, data));
+        true
+    }
+    pub fn move_to(&mut self, from: usize, to: usize) -> bool {
+        match self.delete_at(from) {
+            Some(data) => {
+                self.insert_at(to, data);
+                true
+            }
+            None => false,
+        }
+    }
+    pub fn duplicate_at(&mut self, index: usize) -> bool {
+        match self.data.get(index) {
+            Some(data) => {
+                let data = data.clone();
+                self.insert_at(index, data);
+                true
+            }
+            None => false,
+        }
+    }
+    pub fn clipboard_clear(&mut self) {
+        if !self.data.is_empty() {
+            let groups = self.groups_of_front(self.data.len());
+            let lines = self.data.iter().cloned().collect();
+            self.push_redo(Removed::Clear { lines, groups });
+        }
+        self.data.clear();
+        self.copied_lines.clear();
+        self.add_line_count = 0;
+        self.cursor = 0;
+        self.write_journal(Record::Clear);
+    }
+    pub fn get_clipboard_lines(&self) -> usize {
+        self.data.len()
+    }
+    pub fn undo_data(&mut self) -> usize {
+        let lines = self.copied_lines.len();
+        if lines == 0 {
+            return 0;
+        }
+        self.remove_data(self.copied_lines[lines - 1])
+    }
+    pub fn remove_data(&mut self, delete_count: usize) -> usize {
+        let data_total = self.data.len();
+
+        if data_total == 0 {
+            return 0;
+        }
+        let groups = self.groups_of_front(delete_count.min(data_total));
+        let mut removed_lines = Vec::new();
+        let mut actual_total_deletes = 0;
+        for i in 0..delete_count {
+            if i < data_total {
+                removed_lines.extend(self.data.pop_front());
+                actual_total_deletes += 1;
+            } else {
+                break;
+            }
+        }
+        if !removed_lines.is_empty() {
+            self.push_redo(Removed::Front {
+                lines: removed_lines,
+                groups,
+            });
+        }
+        let e = self.copied_lines.len();
+        let mut total_deletes = actual_total_deletes;
+        for _i in 0..e {
+            let lines = self.copied_lines.pop().unwrap();
+            if lines <= total_deletes {
+                total_deletes -= lines
+            } else {
+                self.copied_lines.push(lines - total_deletes);
+                break;
+            };
+        }
+        self.write_journal(Record::Remove(actual_total_deletes));
+        actual_total_deletes
+    }
+    // 先頭からcount行に含まれるコピー単位ごとの行数（古い順）
+    fn groups_of_front(&self, count: usize) -> Vec<usize> {
+        let mut groups: Vec<usize> = Vec::new();
+        let mut last_group = None;
+        for (no, (group, _)) in self.entries().iter().take(count).enumerate() {
+            if no != 0 && *group == last_group {
+                *groups.last_mut().unwrap() += 1;
+            } else {
+                groups.push(1);
+                last_group = *group;
+            }
+        }
+        groups.reverse();
+        groups
+    }
+    fn push_redo(&mut self, removed: Removed) {
+        if self.redo.len() >= REDO_HISTORY {
+            self.redo.pop_front();
+        }
+        self.redo.push_back(removed);
+    }
+    // 最後に削除した行を元に戻す。戻した(行数, コピー単位の数)を返す。
+    // アンドゥで削除した行はキューの先頭（最後にコピーした位置）に、クリアした行は末尾（次にペーストされる位置）に戻す。
+    pub fn redo_data(&mut self) -> Option<(usize, usize)> {
+        let removed = self.redo.pop_back()?;
+        let mut redo = std::mem::take(&mut self.redo);
+        let result = match removed {
+            Removed::Front { lines, groups } => {
+                let mut lines = lines.into_iter().rev();
+                for size in &groups {
+                    for line in lines.by_ref().take(*size) {
+                        self.add_clipboard(line);
+                    }
+                    self.commit_copy_lines();
+                }
+                (groups.iter().sum(), groups.len())
+            }
+            Removed::Clear { lines, groups } => {
+                let mut lines = lines.into_iter();
+                for size in groups.iter().rev() {
+                    self.push_group_back(lines.by_ref().take(*size).collect());
+                }
+                (groups.iter().sum(), groups.len())
+            }
+        };
+        // 元に戻すための操作で履歴が増えないようにする
+        std::mem::swap(&mut self.redo, &mut redo);
+        Some(result)
+    }
+    // 1回分のコピーとして、キューの末尾に行を追加する（linesは先頭側から順）
+    fn push_group_back(&mut self, lines: Vec<String>) {
+        if lines.is_empty() {
+            return;
+        }
+        for line in &lines {
+            self.data.push_back(line.clone());
+        }
+        self.copied_lines.insert(0, lines.len());
+        self.write_journal(Record::GroupBack(lines));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn temp_path(name: &str) -> String {
+        let path = std::env::temp_dir().join(format!("queue-{}-{name}", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        path.to_string_lossy().into_owned()
+    }
+    fn lines(texts: &[&str]) -> Vec<String> {
+        texts.iter().map(|text| text.to_string()).collect()
+    }
+    // ペーストされる順（古い順）の行
+    fn paste_order(queue: &ClipboardData) -> Vec<String> {
+        queue.entries().iter().rev().map(|(_, line)| (*line).clone()).collect()
+    }
+
+    #[test]
+    fn moved_line_is_the_next_one_pasted_in_every_order() {
+        for order in [QueueOrder::Fifo, QueueOrder::Lifo, QueueOrder::GroupReversed] {
+            let mut queue = ClipboardData::new();
+            let limit = QueueLimit::default();
+            queue.add_copy(lines(&["a", "b", "c"]), &limit).unwrap();
+            queue.add_copy(lines(&["d", "e"]), &limit).unwrap();
+            for no in 0..queue.get_clipboard_lines() {
+                let mut moved = ClipboardData::new();
+                moved.add_copy(lines(&["a", "b", "c"]), &limit).unwrap();
+                moved.add_copy(lines(&["d", "e"]), &limit).unwrap();
+                let line = moved.entries()[no].1.clone();
+                assert!(moved.move_to_next(no, order, false));
+                assert_eq!(moved.take(order), Some(line), "{order:?} {no}");
+            }
+        }
+    }
+
+    #[test]
+    fn moved_line_is_at_the_cursor_in_sticky_mode() {
+        let mut queue = ClipboardData::new();
+        queue
+            .add_copy(lines(&["a", "b", "c", "d"]), &QueueLimit::default())
+            .unwrap();
+        queue.move_cursor(true);
+        assert_eq!(queue.line_at_cursor().as_deref(), Some("b"));
+        for no in 0..4 {
+            let line = queue.entries()[no].1.clone();
+            assert!(queue.move_to_next(no, QueueOrder::Fifo, true));
+            assert_eq!(queue.line_at_cursor(), Some(line));
+        }
+    }
+
+    fn limit(max_entries: usize, max_bytes: usize, on_limit: OnLimit) -> QueueLimit {
+        QueueLimit {
+            max_entries,
+            max_bytes,
+            max_entry_length: 3,
+            on_limit,
+        }
+    }
+
+    #[test]
+    fn refused_copy_leaves_the_queue_unchanged() {
+        let mut queue = ClipboardData::new();
+        let limit = limit(3, 0, OnLimit::Refuse);
+        queue.add_copy(lines(&["a", "b"]), &limit).unwrap();
+        assert!(queue.add_copy(lines(&["c", "d"]), &limit).is_err());
+        assert!(queue.add_copy(lines(&["long"]), &limit).is_err());
+        assert_eq!(paste_order(&queue), ["a", "b"]);
+    }
+
+    #[test]
+    fn truncated_copy_keeps_the_lines_that_fit() {
+        let mut queue = ClipboardData::new();
+        let limit = limit(0, 6, OnLimit::Truncate);
+        queue.add_copy(lines(&["ab"]), &limit).unwrap();
+        let report = queue.add_copy(lines(&["long\n", "cd", "ef"]), &limit).unwrap();
+        assert_eq!((report.added, report.truncated, report.dropped), (1, 1, 2));
+        assert_eq!(paste_order(&queue), ["ab", "lon\n"]);
+    }
+
+    #[test]
+    fn evicting_removes_old_lines_in_one_journal_record() {
+        let path = temp_path("evict");
+        let (journal, _) = Journal::open(&path).unwrap();
+        let mut queue = ClipboardData::new();
+        queue.attach_journal(journal);
+        let limit = limit(4, 0, OnLimit::Evict);
+        queue.add_copy(lines(&["a", "b", "c"]), &limit).unwrap();
+        let report = queue.add_copy(lines(&["d", "e", "f"]), &limit).unwrap();
+        assert_eq!((report.added, report.evicted, report.dropped), (3, 2, 0));
+        assert_eq!(paste_order(&queue), ["c", "d", "e", "f"]);
+        let journal = std::fs::read_to_string(&path).unwrap();
+        let evicts: Vec<&str> = journal.lines().filter(|line| line.starts_with("evict")).collect();
+        assert_eq!(evicts, ["evict\t2"]);
+        assert!(!journal.lines().any(|line| line == "pop"));
+
+        let (_, records) = Journal::open(&path).unwrap();
+        let mut restored = ClipboardData::new();
+        restored.restore(records);
+        assert_eq!(paste_order(&restored), ["c", "d", "e", "f"]);
+        // 取り除いた行の残りも1つのコピー単位のままアンドゥできる
+        assert_eq!(restored.undo_data(), 3);
+        assert_eq!(restored.undo_data(), 1);
+        let _ = std::fs::remove_file(&path);
+    }
+
+    #[test]
+    fn copied_lines_are_dropped_when_evicting_everything_is_not_enough() {
+        let mut queue = ClipboardData::new();
+        let limit = limit(0, 4, OnLimit::Evict);
+        queue.add_copy(lines(&["ab", "c"]), &limit).unwrap();
+        let report = queue.add_copy(lines(&["1", "2", "3", "4", "5"]), &limit).unwrap();
+        assert_eq!((report.added, report.evicted, report.dropped), (4, 2, 1));
+        assert_eq!(paste_order(&queue), ["2", "3", "4", "5"]);
+        assert_eq!(queue.undo_data(), 4);
+        assert!(paste_order(&queue).is_empty());
+    }
+
+    #[test]
+    fn register_added_after_startup_keeps_its_journal() {
+        let path = temp_path("registers");
+        let journal_path = register_journal_path(&path, "ids");
+        let mut registers = Registers::new();
+        registers.set_journal_path(&path, RestoreQueue::Ask);
+        registers
+            .get("ids")
+            .add_copy(lines(&["1", "2"]), &QueueLimit::default())
+            .unwrap();
+        drop(registers);
+
+        let mut registers = Registers::new();
+        registers.set_journal_path(&path, RestoreQueue::Ask);
+        let queue = registers.get("ids");
+        assert_eq!(paste_order(queue), ["1", "2"]);
+        assert_eq!(queue.undo_data(), 2);
+        let _ = std::fs::remove_file(&journal_path);
+    }
+}
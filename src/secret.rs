@@ -0,0 +1,119 @@
+// パスワードマネージャーなどが機密として指定したクリップボードの内容を保持する
+// キューやジャーナルには内容の代わりにトークンを積み、ペーストする時だけトークンから内容を取り出す。
+// 保持している内容は取り除いた時にゼロクリアされる。
+use once_cell::unsync::*;
+use serde_derive::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+use zeroize::Zeroizing;
+
+// トークンの先頭に付ける私用領域の文字
+const TOKEN_PREFIX: &str = "\u{E000}secret:";
+// コンソールに表示する代わりの文字列
+pub const MASK: &str = "＊＊＊＊（機密）";
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum OnSensitive {
+    // キューに取り込まない
+    #[default]
+    Skip,
+    // 機密として取り込む
+    Flag,
+    // 通常のコピーと同じく取り込む
+    Capture,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Default)]
+#[serde(default)]
+pub struct SensitiveConfig {
+    pub on_sensitive: OnSensitive,
+    // 機密として取り込んだ行を破棄するまでの秒数（0の場合は破棄しない）
+    pub expire_sec: u64,
+}
+
+struct Secret {
+    text: Zeroizing<String>,
+    expires_at: Option<Instant>,
+}
+
+static mut SECRETS: Lazy<Mutex<HashMap<String, Secret>>> = Lazy::new(|| Mutex::new(HashMap::new()));
+static mut NEXT_ID: Lazy<Mutex<u64>> = Lazy::new(|| {
+    // 別のプロセスのトークンと衝突しないよう、起動時刻から始める
+    let seed = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map(|d| d.as_nanos() as u64)
+        .unwrap_or_default();
+    Mutex::new(seed)
+});
+
+pub fn is_token(line: &str) -> bool {
+    line.starts_with(TOKEN_PREFIX)
+}
+
+// 内容を保持し、キューに積むトークンを返す
+pub fn store(text: String, expire_sec: u64) -> String {
+    let token = {
+        let mut id = unsafe { NEXT_ID.lock().unwrap() };
+        *id = id.wrapping_add(1);
+        format!("{TOKEN_PREFIX}{:016x}", *id)
+    };
+    let expires_at = match expire_sec {
+        0 => None,
+        sec => Some(Instant::now() + Duration::from_secs(sec)),
+    };
+    let secret = Secret {
+        text: Zeroizing::new(text),
+        expires_at,
+    };
+    unsafe { SECRETS.lock().unwrap().insert(token.clone(), secret) };
+    token
+}
+
+// トークンから内容を取り出す。期限切れなどで破棄されている場合はNone
+pub fn reveal(token: &str) -> Option<Zeroizing<String>> {
+    let secrets = unsafe { SECRETS.lock().unwrap() };
+    let secret = secrets.get(token)?;
+    if secret.expires_at.map_or(false, |at| at <= Instant::now()) {
+        return None;
+    }
+    Some(Zeroizing::new(String::clone(&secret.text)))
+}
+
+// コンソールに表示する文字列（トークンは伏せ字にする）
+pub fn mask(line: &str) -> &str {
+    if is_token(line) {
+        MASK
+    } else {
+        line
+    }
+}
+// 機密として扱う内容であれば、トークンでなくても伏せ字にする
+pub fn mask_sensitive(text: &str, sensitive: bool) -> &str {
+    if sensitive {
+        MASK
+    } else {
+        mask(text)
+    }
+}
+
+pub fn is_empty() -> bool {
+    unsafe { SECRETS.lock().unwrap().is_empty() }
+}
+
+// 期限切れ、またはどこからも参照されていない内容を破棄（ゼロクリア）し、期限切れになったトークンを返す
+pub fn purge(is_referenced: impl Fn(&str) -> bool) -> Vec<String> {
+    let now = Instant::now();
+    let mut expired = Vec::new();
+    let mut secrets = unsafe { SECRETS.lock().unwrap() };
+    secrets.retain(|token, secret| {
+        if secret.expires_at.map_or(false, |at| at <= now) {
+            expired.push(token.clone());
+            false
+        } else {
+            is_referenced(token)
+        }
+    });
+    expired
+}
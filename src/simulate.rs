@@ -0,0 +1,289 @@
+// モックのバックエンドでエンジンを動かし、シナリオファイルに書かれた操作を再生する。
+// ホットキーやバーストモードの挙動を、実際のキーボードやクリップボードを使わずに確認するためのもの。
+use crate::clipboard::{self, ClipboardWrite, MemoryClipboard};
+use crate::config::{Config, InputMode, RunMode};
+use crate::default;
+use crate::hook::{self, DegradedHookController, MockHookController};
+use crate::injector::{self, InjectedKey, RecordingInjector};
+use crate::keycode::*;
+use crate::secret;
+use crate::window::{self, MockWindowInfoProvider, WindowInfo};
+use multiline_parser_pluginlib::result::PluginResult;
+use serde_derive::Deserialize;
+use std::time::{Duration, Instant};
+use zeroize::{Zeroize, Zeroizing};
+
+#[derive(Debug, Deserialize)]
+pub struct Scenario {
+    // コマンドラインの --clipboard / --burst と同じ
+    #[serde(default)]
+    pub clipboard: bool,
+    #[serde(default)]
+    pub burst: bool,
+    // inputctl.dll が無い環境と同じ縮退モードで動作させる
+    #[serde(default)]
+    pub degraded: bool,
+    // logic_config.toml と同じ形式。省略した場合は既定値
+    pub config: Option<Config>,
+    // キーイベントの後、非同期に実行されるコピー・ペースト処理が終わるのを待つ時間
+    #[serde(default = "default_settle_msec")]
+    pub settle_msec: u64,
+    #[serde(default)]
+    pub steps: Vec<Step>,
+}
+fn default_settle_msec() -> u64 {
+    100
+}
+impl Scenario {
+    pub fn load(path: &str) -> Result<Scenario, String> {
+        let text = std::fs::read_to_string(path).map_err(|e| e.to_string())?;
+        toml::from_str(&text).map_err(|e| e.to_string())
+    }
+}
+
+// 1つのステップに複数の操作を書いた場合は、このフィールドの順番に実行する。
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+pub struct Step {
+    // アクティブウィンドウを切り替える
+    pub window: Option<WindowInfo>,
+    // アクティブウィンドウが無い状態にする
+    pub focus_lost: bool,
+    pub key_down: Option<String>,
+    // "CONTROL+V" のように+で繋げたキーを順に押し、逆順に離す
+    pub press: Option<String>,
+    pub key_up: Option<String>,
+    // 他のアプリケーションがクリップボードに書き込んだ（update_clipboardが通知される）
+    pub set_clipboard: Option<String>,
+    // set_clipboard の内容をパスワードマネージャーが機密として指定した
+    pub secret: bool,
+    // 待機時間（ミリ秒）
+    pub wait: Option<u64>,
+}
+
+pub struct KeyLog {
+    pub step: usize,
+    pub vk: usize,
+    pub down: bool,
+    pub result: PluginResult,
+}
+
+pub struct Report {
+    pub key_events: Vec<KeyLog>,
+    pub injected: Vec<InjectedKey>,
+    pub clipboard_writes: Vec<ClipboardWrite>,
+    pub clipboard_text: Option<String>,
+    pub clipboard_sensitive: bool,
+}
+impl Report {
+    pub fn print(&self) {
+        println!("🎹  キーイベント:");
+        for log in &self.key_events {
+            let result = match log.result {
+                PluginResult::NoChain => "握りつぶし",
+                _ => "通過",
+            };
+            println!(
+                "  [{}] {} {} -> {}",
+                log.step,
+                vk_name(log.vk),
+                ["↑", "↓"][log.down as usize],
+                result
+            );
+        }
+        println!("⌨  送信されたキーストローク:");
+        for line in format_injected(&self.injected) {
+            println!("  {line}");
+        }
+        println!("📋  クリップボードへの書き込み:");
+        for (i, write) in self.clipboard_writes.iter().enumerate() {
+            println!(
+                "  [{}] {:?}",
+                i + 1,
+                secret::mask_sensitive(&write.text, write.sensitive)
+            );
+        }
+        println!(
+            "📋  最終的なクリップボードの内容: {:?}",
+            self.clipboard_text
+                .as_deref()
+                .map(|text| secret::mask_sensitive(text, self.clipboard_sensitive))
+        );
+    }
+}
+
+// 文字の入力はまとめて1つの文字列として、待機が入るごとに改行して表示する。
+// 機密の行の文字は伏せ字にする。
+fn format_injected(events: &[InjectedKey]) -> Vec<String> {
+    let mut lines = Vec::new();
+    let mut line = Vec::new();
+    let mut text = Vec::new();
+    let mut sensitive = false;
+    let flush_text = |text: &mut Vec<u16>, sensitive: bool, line: &mut Vec<String>| {
+        if !text.is_empty() {
+            let s = Zeroizing::new(String::from_utf16_lossy(text));
+            line.push(format!("{:?}", secret::mask_sensitive(&s, sensitive)));
+            text.zeroize();
+        }
+    };
+    for event in events {
+        let is_secret = matches!(event, InjectedKey::SecretChar(_));
+        if is_secret != sensitive {
+            flush_text(&mut text, sensitive, &mut line);
+            sensitive = is_secret;
+        }
+        match event {
+            InjectedKey::SecretChar(scan_code) => text.push(*scan_code),
+            InjectedKey::Down { vk: 0, scan_code } => text.push(*scan_code),
+            InjectedKey::Up { vk: 0, .. } => {}
+            InjectedKey::Down { vk, .. } => {
+                flush_text(&mut text, sensitive, &mut line);
+                line.push(format!("[{}↓]", vk_name(*vk)));
+            }
+            InjectedKey::Up { vk, .. } => {
+                flush_text(&mut text, sensitive, &mut line);
+                line.push(format!("[{}↑]", vk_name(*vk)));
+            }
+            InjectedKey::Delay(msec) => {
+                flush_text(&mut text, sensitive, &mut line);
+                line.push(format!("({msec}ms)"));
+                lines.push(line.join(" "));
+                line.clear();
+            }
+        }
+    }
+    flush_text(&mut text, sensitive, &mut line);
+    if !line.is_empty() {
+        lines.push(line.join(" "));
+    }
+    lines
+}
+
+fn parse_keys(keys: &str) -> Result<Vec<usize>, String> {
+    keys.split('+')
+        .map(|name| vk_from_name(name).ok_or_else(|| format!("不明なキー名です: {name}")))
+        .collect()
+}
+
+// シナリオを再生する。エンジンの状態はプロセス全体で共有されるので、1プロセスにつき1回だけ呼び出すこと。
+pub fn run(scenario: &Scenario) -> Result<Report, String> {
+    let cb = MemoryClipboard::new();
+    let keys = RecordingInjector::new();
+    let active_window = MockWindowInfoProvider::new();
+    clipboard::set_clipboard_backend(Box::new(cb.clone()));
+    injector::set_key_injector(Box::new(keys.clone()));
+    window::set_window_info_provider(Box::new(active_window.clone()));
+    if scenario.degraded {
+        hook::set_hook_controller(Box::new(DegradedHookController::new(
+            "シナリオで縮退モードが指定されています".to_owned(),
+        )));
+    } else {
+        hook::set_hook_controller(Box::new(MockHookController::new()));
+    }
+
+    let mut config = scenario.config.clone().unwrap_or_default();
+    config.check()?;
+    let mut mode = RunMode::new();
+    mode.set_burst_mode(scenario.burst);
+    mode.set_input_mode(if scenario.clipboard {
+        InputMode::Clipboard
+    } else {
+        InputMode::DirectKeyInput
+    });
+    mode.set_config(config.clone());
+    hook::sethook();
+    default::eh_init();
+    if let Some(encoder_list) = config.text_modifiers {
+        default::load_encoder(encoder_list);
+    }
+    default::set_mode(mode);
+
+    let start = Instant::now();
+    let settle = Duration::from_millis(scenario.settle_msec);
+    let mut key_events = Vec::new();
+    let mut send_key = |step: usize, vk: usize, down: bool| {
+        let mut event = KeyEvent::new(vk);
+        event.time = start.elapsed().as_millis() as u64;
+        let result = if down {
+            default::key_down(0, &event)
+        } else {
+            default::key_up(0, &event)
+        };
+        key_events.push(KeyLog {
+            step,
+            vk,
+            down,
+            result,
+        });
+        std::thread::sleep(settle);
+    };
+    for (i, step) in scenario.steps.iter().enumerate() {
+        let step_no = i + 1;
+        let error = |e: String| format!("ステップ{step_no}: {e}");
+        if let Some(window) = &step.window {
+            active_window.set_active_window(Some(window.clone()));
+        }
+        if step.focus_lost {
+            active_window.set_active_window(None);
+        }
+        if let Some(key) = &step.key_down {
+            for vk in parse_keys(key).map_err(error)? {
+                send_key(step_no, vk, true);
+            }
+        }
+        if let Some(key) = &step.press {
+            let vks = parse_keys(key).map_err(error)?;
+            for vk in &vks {
+                send_key(step_no, *vk, true);
+            }
+            for vk in vks.iter().rev() {
+                send_key(step_no, *vk, false);
+            }
+        }
+        if let Some(key) = &step.key_up {
+            for vk in parse_keys(key).map_err(error)? {
+                send_key(step_no, vk, false);
+            }
+        }
+        if let Some(text) = &step.set_clipboard {
+            if step.secret {
+                cb.copy_secret_from_outside(text);
+            } else {
+                cb.copy_from_outside(text);
+            }
+            default::update_clipboard();
+            std::thread::sleep(settle);
+        }
+        if let Some(msec) = step.wait {
+            std::thread::sleep(Duration::from_millis(msec));
+        }
+    }
+    std::thread::sleep(settle);
+
+    let state = cb.state();
+    Ok(Report {
+        key_events,
+        injected: keys.take_events(),
+        clipboard_writes: state.writes.clone(),
+        clipboard_text: state.text.clone(),
+        clipboard_sensitive: state.sensitive,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn secret_chars_are_masked_in_the_report() {
+        let mut kbd = KeySequence::new();
+        kbd.push_str("id");
+        let mut keys = RecordingInjector::new();
+        injector::KeyInjector::send(&mut keys, &kbd);
+        kbd.clear();
+        kbd.set_sensitive(true);
+        kbd.push_str("p\"w");
+        injector::KeyInjector::send(&mut keys, &kbd);
+        assert_eq!(format_injected(&keys.take_events()), [format!("\"id\" {:?}", secret::MASK)]);
+    }
+}
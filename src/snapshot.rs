@@ -0,0 +1,189 @@
+// キューの内容をJSON/CSVのファイルに書き出し、書き出したファイルからキューを復元する
+// 拡張子が .csv の場合はCSV、それ以外はJSONとして扱う。
+use crate::default::with_queue;
+use crate::queue::ClipboardData;
+use crate::secret;
+use crate::splitter::{encode_record, split_fields, split_line_ending, RecordSplitter, Splitter};
+use serde_derive::{Deserialize, Serialize};
+use std::path::Path;
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Snapshot {
+    pub register: String,
+    pub exported_at: String,
+    pub entries: Vec<SnapshotEntry>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct SnapshotEntry {
+    // ペーストされる順番（1が次にペーストされる行）
+    pub order: usize,
+    // コピーした単位の番号（1が最も古い）。未確定の行はNone
+    pub group: Option<usize>,
+    // 行末の改行（crlf / lf / none）
+    #[serde(default)]
+    pub line_ending: String,
+    // 以下は復元には使用しない
+    #[serde(default)]
+    pub chars: usize,
+    pub text: String,
+}
+
+const CSV_HEADER: [&str; 5] = ["order", "group", "line_ending", "chars", "text"];
+
+fn is_csv(path: &str) -> bool {
+    Path::new(path)
+        .extension()
+        .map_or(false, |ext| ext.eq_ignore_ascii_case("csv"))
+}
+
+// 機密の行は書き出さない（順番の番号は欠番になる）
+pub fn take_snapshot(queue: &ClipboardData, register: &str) -> Snapshot {
+    let entries = queue
+        .entries()
+        .into_iter()
+        .rev()
+        .enumerate()
+        .filter(|(_, (_, text))| !secret::is_token(text))
+        .map(|(no, (group, text))| SnapshotEntry {
+            order: no + 1,
+            group: group.map(|group| group + 1),
+            line_ending: match split_line_ending(text).1 {
+                "\r\n" => "crlf",
+                "\n" => "lf",
+                _ => "none",
+            }
+            .to_owned(),
+            chars: text.chars().count(),
+            text: text.clone(),
+        })
+        .collect();
+    Snapshot {
+        register: register.to_owned(),
+        exported_at: chrono::Local::now().to_rfc3339(),
+        entries,
+    }
+}
+
+pub fn export(path: &str, snapshot: &Snapshot) -> Result<(), String> {
+    let text = if is_csv(path) {
+        let header: Vec<String> = CSV_HEADER.iter().map(|s| s.to_string()).collect();
+        let mut lines = vec![encode_record(&header, ',')];
+        for entry in &snapshot.entries {
+            let fields = [
+                entry.order.to_string(),
+                entry
+                    .group
+                    .map(|group| group.to_string())
+                    .unwrap_or_default(),
+                entry.line_ending.clone(),
+                entry.chars.to_string(),
+                entry.text.clone(),
+            ];
+            lines.push(encode_record(&fields, ','));
+        }
+        lines.join("\n") + "\n"
+    } else {
+        serde_json::to_string_pretty(snapshot).map_err(|e| e.to_string())?
+    };
+    std::fs::write(path, text).map_err(|e| e.to_string())
+}
+
+pub fn load(path: &str) -> Result<Vec<SnapshotEntry>, String> {
+    let text = std::fs::read_to_string(path).map_err(|e| e.to_string())?;
+    if !is_csv(path) {
+        let snapshot: Snapshot = serde_json::from_str(&text).map_err(|e| e.to_string())?;
+        return Ok(snapshot.entries);
+    }
+    let mut entries = Vec::new();
+    for (no, record) in RecordSplitter::new(',').split(&text).iter().enumerate().skip(1) {
+        // 書き出した行の改行をそのまま復元するため、parse_recordのようにCRLFを変換しない
+        let fields = split_fields(record, ',');
+        if fields.len() != CSV_HEADER.len() {
+            return Err(format!("{}行目の列の数が正しくありません", no + 1));
+        }
+        let number = |field: &str| {
+            field
+                .parse::<usize>()
+                .map_err(|e| format!("{}行目: {e}", no + 1))
+        };
+        entries.push(SnapshotEntry {
+            order: number(&fields[0])?,
+            group: match fields[1].as_str() {
+                "" => None,
+                group => Some(number(group)?),
+            },
+            line_ending: fields[2].clone(),
+            chars: number(&fields[3])?,
+            text: fields[4].clone(),
+        });
+    }
+    Ok(entries)
+}
+
+// キューを消去してスナップショットの内容に置き換える（消去した内容はやり直しで戻せる）
+pub fn restore(queue: &mut ClipboardData, mut entries: Vec<SnapshotEntry>) -> usize {
+    entries.sort_by_key(|entry| entry.order);
+    queue.clipboard_clear();
+    // ペーストされる順（古い順）に積み、コピー単位が変わったところで確定する
+    let mut current = None;
+    for entry in &entries {
+        if current.is_some() && entry.group != current {
+            queue.commit_copy_lines();
+        }
+        current = entry.group;
+        queue.add_clipboard(entry.text.clone());
+    }
+    if current.is_some() {
+        queue.commit_copy_lines();
+    }
+    entries.len()
+}
+
+pub fn export_register(path: &str, register: &str) -> Result<(), String> {
+    let snapshot = with_queue(register, |queue| take_snapshot(queue, register));
+    export(path, &snapshot)?;
+    println!("📤  {} 行を {path} に書き出しました。", snapshot.entries.len());
+    Ok(())
+}
+pub fn load_register(path: &str, register: &str) -> Result<(), String> {
+    let entries = load(path)?;
+    let lines = with_queue(register, |queue| restore(queue, entries));
+    println!("📥  {path} から {lines} 行を復元しました。");
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::queue::QueueLimit;
+
+    fn contents(queue: &ClipboardData) -> Vec<(Option<usize>, String)> {
+        queue
+            .entries()
+            .into_iter()
+            .map(|(group, text)| (group, text.clone()))
+            .collect()
+    }
+
+    #[test]
+    fn exported_queue_is_restored_with_its_groups_and_line_endings() {
+        let mut queue = ClipboardData::new();
+        let limit = QueueLimit::default();
+        let lines = |texts: &[&str]| texts.iter().map(|text| text.to_string()).collect();
+        queue.add_copy(lines(&["a\r\n", "b,\"q\"\n", "c"]), &limit).unwrap();
+        queue.add_copy(lines(&["first\r\nsecond\r\n", "tab\there"]), &limit).unwrap();
+        queue.add_clipboard("pending".to_owned());
+        for ext in ["json", "csv"] {
+            let name = format!("snapshot-{}.{ext}", std::process::id());
+            let path = std::env::temp_dir().join(name);
+            let path = path.to_string_lossy();
+            export(&path, &take_snapshot(&queue, "default")).unwrap();
+            let entries = load(&path).unwrap();
+            let _ = std::fs::remove_file(path.as_ref());
+            let mut restored = ClipboardData::new();
+            assert_eq!(restore(&mut restored, entries), 6);
+            assert_eq!(contents(&restored), contents(&queue), "{ext}");
+        }
+    }
+}
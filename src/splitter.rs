@@ -0,0 +1,537 @@
+// コピーしたテキストをキューに積む単位に分割する
+use regex::Regex;
+use serde_derive::{Deserialize, Serialize};
+use zeroize::Zeroize;
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum SplitRule {
+    // 1行ずつ
+    #[default]
+    Line,
+    // 空行で区切られた段落ごと
+    Paragraph,
+    // 正規表現（split_regex）に一致する部分で区切る
+    Regex,
+    // 各行を固定の文字数（split_width）ごとに区切る
+    Width,
+    // 分割せずにクリップボードの内容を1つにまとめる
+    None,
+}
+impl SplitRule {
+    pub fn next(self) -> Self {
+        match self {
+            SplitRule::Line => SplitRule::Paragraph,
+            SplitRule::Paragraph => SplitRule::Regex,
+            SplitRule::Regex => SplitRule::Width,
+            SplitRule::Width => SplitRule::None,
+            SplitRule::None => SplitRule::Line,
+        }
+    }
+    // 設定ファイルでの名前
+    pub fn config_name(self) -> &'static str {
+        match self {
+            SplitRule::Line => "line",
+            SplitRule::Paragraph => "paragraph",
+            SplitRule::Regex => "regex",
+            SplitRule::Width => "width",
+            SplitRule::None => "none",
+        }
+    }
+    pub fn name(self) -> &'static str {
+        match self {
+            SplitRule::Line => "行",
+            SplitRule::Paragraph => "段落",
+            SplitRule::Regex => "正規表現",
+            SplitRule::Width => "固定幅",
+            SplitRule::None => "分割しない",
+        }
+    }
+}
+
+pub trait Splitter {
+    fn split(&self, text: &str) -> Vec<String>;
+}
+
+pub struct LineSplitter;
+impl Splitter for LineSplitter {
+    fn split(&self, text: &str) -> Vec<String> {
+        text.lines().map(|line| line.to_owned()).collect()
+    }
+}
+
+// 行末の改行を含めたまま1行ずつ分割する（line_ending = "keep"）
+pub struct TerminatedLineSplitter;
+impl Splitter for TerminatedLineSplitter {
+    fn split(&self, text: &str) -> Vec<String> {
+        text.split_inclusive('\n').map(|line| line.to_owned()).collect()
+    }
+}
+
+pub struct ParagraphSplitter;
+impl Splitter for ParagraphSplitter {
+    fn split(&self, text: &str) -> Vec<String> {
+        let mut paragraphs = Vec::new();
+        let mut paragraph: Vec<&str> = Vec::new();
+        for line in text.lines() {
+            if line.trim().is_empty() {
+                if !paragraph.is_empty() {
+                    paragraphs.push(paragraph.join("\n"));
+                    paragraph.clear();
+                }
+            } else {
+                paragraph.push(line);
+            }
+        }
+        if !paragraph.is_empty() {
+            paragraphs.push(paragraph.join("\n"));
+        }
+        paragraphs
+    }
+}
+
+pub struct RegexSplitter {
+    regex: Regex,
+}
+impl Splitter for RegexSplitter {
+    fn split(&self, text: &str) -> Vec<String> {
+        let mut pieces: Vec<String> = self.regex.split(text).map(|s| s.to_owned()).collect();
+        // str::lines() と同じく、末尾の区切りの後ろは要素にしない
+        if pieces.last().map_or(false, |s| s.is_empty()) {
+            pieces.pop();
+        }
+        pieces
+    }
+}
+
+pub struct WidthSplitter {
+    width: usize,
+}
+impl Splitter for WidthSplitter {
+    fn split(&self, text: &str) -> Vec<String> {
+        let mut pieces = Vec::new();
+        for line in text.lines() {
+            if line.is_empty() {
+                pieces.push(String::new());
+            }
+            // 文字の境界で区切った部分をそのまま切り出す
+            let mut start = 0;
+            for (no, (index, _)) in line.char_indices().enumerate() {
+                if no != 0 && no % self.width == 0 {
+                    pieces.push(line[start..index].to_owned());
+                    start = index;
+                }
+            }
+            if start < line.len() {
+                pieces.push(line[start..].to_owned());
+            }
+        }
+        pieces
+    }
+}
+
+pub struct WholeSplitter;
+impl Splitter for WholeSplitter {
+    fn split(&self, text: &str) -> Vec<String> {
+        if text.is_empty() {
+            Vec::new()
+        } else {
+            vec![text.to_owned()]
+        }
+    }
+}
+
+// 設定が不正な場合は理由を返す
+pub fn build_splitter(
+    rule: SplitRule,
+    pattern: &str,
+    width: usize,
+    keep_line_endings: bool,
+) -> Result<Box<dyn Splitter>, String> {
+    // 段落・正規表現・固定幅の区切りでは元の改行が要素の末尾に残らない
+    if keep_line_endings && !matches!(rule, SplitRule::Line | SplitRule::None) {
+        return Err(format!(
+            "line_ending = \"keep\" は split_rule = \"{}\" と併用できません",
+            rule.config_name()
+        ));
+    }
+    let splitter: Box<dyn Splitter> = match rule {
+        SplitRule::Line if keep_line_endings => Box::new(TerminatedLineSplitter),
+        SplitRule::Line => Box::new(LineSplitter),
+        SplitRule::Paragraph => Box::new(ParagraphSplitter),
+        SplitRule::Regex => {
+            if pattern.is_empty() {
+                return Err("split_regex が指定されていません".to_owned());
+            }
+            let regex =
+                Regex::new(pattern).map_err(|e| format!("split_regex が不正です: {e}"))?;
+            Box::new(RegexSplitter { regex })
+        }
+        SplitRule::Width => {
+            if width == 0 {
+                return Err("split_width には1以上を指定してください".to_owned());
+            }
+            Box::new(WidthSplitter { width })
+        }
+        SplitRule::None => Box::new(WholeSplitter),
+    };
+    Ok(splitter)
+}
+
+// 各行の末尾の改行の扱い
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum LineEnding {
+    // 改行を含めない
+    #[default]
+    Strip,
+    // コピーした時の改行（CRLF/LF/無し）をそのままペーストする
+    Keep,
+    // すべての行の末尾をLFにする
+    Lf,
+    // すべての行の末尾をCRLFにする
+    Crlf,
+}
+
+// 各行の空白の扱い
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum Whitespace {
+    #[default]
+    Keep,
+    // 前後の空白を取り除く
+    Trim,
+    // 前後の空白を取り除き、連続した空白を1つのスペースにする
+    Normalize,
+}
+
+// 行末の改行とそれ以外に分ける
+pub fn split_line_ending(line: &str) -> (&str, &str) {
+    if let Some(body) = line.strip_suffix("\r\n") {
+        (body, "\r\n")
+    } else if let Some(body) = line.strip_suffix('\n') {
+        (body, "\n")
+    } else {
+        (line, "")
+    }
+}
+
+// 分割した1行に空白と改行の設定を適用する
+// 機密の行の内容が再確保で解放済みの領域に残らないよう、必要な長さを先に確保して組み立てる。
+pub fn finish_entry(entry: &str, line_ending: LineEnding, whitespace: Whitespace) -> String {
+    let (body, ending) = split_line_ending(entry);
+    let ending = match line_ending {
+        LineEnding::Strip => "",
+        LineEnding::Keep => ending,
+        LineEnding::Lf => "\n",
+        LineEnding::Crlf => "\r\n",
+    };
+    let mut finished = String::with_capacity(body.len() + ending.len());
+    match whitespace {
+        Whitespace::Keep => finished.push_str(body),
+        Whitespace::Trim => finished.push_str(body.trim()),
+        Whitespace::Normalize => {
+            for (no, word) in body.split_whitespace().enumerate() {
+                if no != 0 {
+                    finished.push(' ');
+                }
+                finished.push_str(word);
+            }
+        }
+    }
+    finished.push_str(ending);
+    finished
+}
+
+// コピーしたデータをTSV/CSVのレコードとして扱う
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum RecordFormat {
+    #[default]
+    Off,
+    Tsv,
+    Csv,
+}
+impl RecordFormat {
+    pub fn delimiter(self) -> Option<char> {
+        match self {
+            RecordFormat::Off => None,
+            RecordFormat::Tsv => Some('\t'),
+            RecordFormat::Csv => Some(','),
+        }
+    }
+}
+
+// レコードごとに分割する。引用符で囲まれた改行はレコードの区切りとして扱わない。
+// フィールドへの分割はペーストする際にparse_recordで行う。
+pub struct RecordSplitter {
+    delimiter: char,
+}
+impl RecordSplitter {
+    pub fn new(delimiter: char) -> Self {
+        RecordSplitter { delimiter }
+    }
+}
+impl Splitter for RecordSplitter {
+    fn split(&self, text: &str) -> Vec<String> {
+        let mut records = Vec::new();
+        let mut push = |record: &str| {
+            records.push(record.strip_suffix('\r').unwrap_or(record).to_owned());
+        };
+        let mut start = 0;
+        let mut chars = text.char_indices().peekable();
+        // parse_recordと同じく、引用符はフィールドの先頭にある場合だけ引用の開始として扱う
+        let mut in_quotes = false;
+        let mut at_start = true;
+        while let Some((i, c)) = chars.next() {
+            if in_quotes {
+                if c == '"' {
+                    if chars.peek().map(|&(_, c)| c) == Some('"') {
+                        chars.next();
+                    } else {
+                        in_quotes = false;
+                    }
+                }
+                continue;
+            }
+            if c == '"' && at_start {
+                in_quotes = true;
+                at_start = false;
+            } else if c == '\n' {
+                push(&text[start..i]);
+                start = i + 1;
+                at_start = true;
+            } else {
+                at_start = c == self.delimiter;
+            }
+        }
+        let rest = &text[start..];
+        if !rest.is_empty() && rest != "\r" {
+            push(rest);
+        }
+        records
+    }
+}
+
+// 1レコードをフィールドに分割する。引用符で囲まれたフィールドでは、区切りと改行をそのまま含め、""を"として扱う。
+// 引用符内の改行はExcelからコピーするとCRLFになっているので、LFにする。
+pub fn parse_record(record: &str, delimiter: char) -> Vec<String> {
+    fields(record, delimiter, false)
+}
+
+// parse_recordと同じく分割するが、改行を変換しない
+pub fn split_fields(record: &str, delimiter: char) -> Vec<String> {
+    fields(record, delimiter, true)
+}
+
+fn fields(record: &str, delimiter: char, keep_crlf: bool) -> Vec<String> {
+    let mut fields = Vec::new();
+    let mut start = 0;
+    let mut chars = record.char_indices().peekable();
+    // 引用符はフィールドの先頭にある場合だけ引用の開始として扱う
+    let mut in_quotes = false;
+    let mut at_start = true;
+    while let Some((i, c)) = chars.next() {
+        if in_quotes {
+            if c == '"' {
+                if chars.peek().map(|&(_, c)| c) == Some('"') {
+                    chars.next();
+                } else {
+                    in_quotes = false;
+                }
+            }
+            continue;
+        }
+        if c == '"' && at_start {
+            in_quotes = true;
+            at_start = false;
+        } else if c == delimiter {
+            fields.push(unquote(&record[start..i], keep_crlf));
+            start = i + c.len_utf8();
+            at_start = true;
+        } else {
+            at_start = false;
+        }
+    }
+    fields.push(unquote(&record[start..], keep_crlf));
+    fields
+}
+
+// 1フィールド分の文字列から引用符を外す。フィールドの内容は元より長くならないので、最初に確保した領域だけを使う。
+fn unquote(raw: &str, keep_crlf: bool) -> String {
+    let mut field = String::with_capacity(raw.len());
+    let mut in_quotes = raw.starts_with('"');
+    let mut chars = raw.chars().skip(in_quotes as usize).peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '"' if in_quotes => {
+                if chars.peek() == Some(&'"') {
+                    chars.next();
+                    field.push('"');
+                } else {
+                    in_quotes = false;
+                }
+            }
+            '\r' if !keep_crlf && chars.peek() == Some(&'\n') => {}
+            c => field.push(c),
+        }
+    }
+    field
+}
+
+// parse_recordの逆。区切り・引用符・改行を含むフィールドは引用符で囲む。
+// finish_entryと同じく、最大の長さを先に確保して組み立てる。
+pub fn encode_record(fields: &[String], delimiter: char) -> String {
+    let capacity = fields
+        .iter()
+        .map(|field| field.len() * 2 + 2 + delimiter.len_utf8())
+        .sum();
+    let mut record = String::with_capacity(capacity);
+    for (no, field) in fields.iter().enumerate() {
+        if no != 0 {
+            record.push(delimiter);
+        }
+        if field.contains(delimiter) || field.contains('"') || field.contains('\n') {
+            record.push('"');
+            for c in field.chars() {
+                if c == '"' {
+                    record.push('"');
+                }
+                record.push(c);
+            }
+            record.push('"');
+        } else {
+            record.push_str(field);
+        }
+    }
+    record
+}
+
+// 表形式のデータから列を選択（1始まり。空の場合はすべての列）し、必要なら行と列を入れ替える。
+// 1列だけになった行はセルの値そのものを、それ以外はレコードとして返す。
+pub fn transform_table(
+    records: &[String],
+    delimiter: char,
+    columns: &[usize],
+    transpose: bool,
+) -> Vec<String> {
+    let mut rows: Vec<Vec<String>> = records
+        .iter()
+        .map(|record| parse_record(record, delimiter))
+        .collect();
+    // 途中の表は機密の行を含むことがあるので、置き換えたらゼロクリアする
+    if !columns.is_empty() {
+        let selected = rows
+            .iter()
+            .map(|row| {
+                columns
+                    .iter()
+                    .map(|no| {
+                        no.checked_sub(1)
+                            .and_then(|index| row.get(index))
+                            .cloned()
+                            .unwrap_or_default()
+                    })
+                    .collect()
+            })
+            .collect();
+        rows.zeroize();
+        rows = selected;
+    }
+    if transpose {
+        let width = rows.iter().map(|row| row.len()).max().unwrap_or(0);
+        let transposed = (0..width)
+            .map(|col| {
+                rows.iter()
+                    .map(|row| row.get(col).cloned().unwrap_or_default())
+                    .collect()
+            })
+            .collect();
+        rows.zeroize();
+        rows = transposed;
+    }
+    rows.into_iter()
+        .map(|mut row| match row.len() {
+            1 => row.pop().unwrap(),
+            _ => {
+                let record = encode_record(&row, delimiter);
+                row.zeroize();
+                record
+            }
+        })
+        .collect()
+}
+
+// "3" や "1,3" のような列番号の指定
+pub fn parse_columns(text: &str) -> Result<Vec<usize>, String> {
+    text.split(',')
+        .map(|no| match no.trim().parse::<usize>() {
+            Ok(no) if no >= 1 => Ok(no),
+            _ => Err(format!("列番号は1以上の数値で指定してください: {no}")),
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn split(rule: SplitRule, pattern: &str, width: usize, text: &str) -> Vec<String> {
+        build_splitter(rule, pattern, width, false).unwrap().split(text)
+    }
+
+    #[test]
+    fn each_rule_splits_the_copied_text() {
+        let text = "ab\r\ncdefg\n\n  \nhi\n";
+        assert_eq!(split(SplitRule::Line, "", 0, text), ["ab", "cdefg", "", "  ", "hi"]);
+        assert_eq!(split(SplitRule::Paragraph, "", 0, text), ["ab\ncdefg", "hi"]);
+        assert_eq!(split(SplitRule::Width, "", 2, text), ["ab", "cd", "ef", "g", "", "  ", "hi"]);
+        assert_eq!(split(SplitRule::None, "", 0, text), [text]);
+        assert_eq!(split(SplitRule::Regex, r"\s*;\s*", 0, "a ; b;c;"), ["a", "b", "c"]);
+        assert!(split(SplitRule::None, "", 0, "").is_empty());
+    }
+
+    #[test]
+    fn width_counts_characters_rather_than_bytes() {
+        assert_eq!(split(SplitRule::Width, "", 2, "日本語"), ["日本", "語"]);
+    }
+
+    #[test]
+    fn invalid_rule_settings_are_reported() {
+        assert!(build_splitter(SplitRule::Regex, "", 0, false).is_err());
+        assert!(build_splitter(SplitRule::Regex, "(", 0, false).is_err());
+        assert!(build_splitter(SplitRule::Width, "", 0, false).is_err());
+        for rule in [SplitRule::Paragraph, SplitRule::Regex, SplitRule::Width] {
+            let e = build_splitter(rule, ",", 2, true).err().unwrap();
+            assert!(e.contains("line_ending"), "{e}");
+        }
+    }
+
+    #[test]
+    fn kept_line_endings_are_preserved_by_line_and_none() {
+        let text = "a\r\nb\nc";
+        let split = |rule| build_splitter(rule, "", 0, true).unwrap().split(text);
+        assert_eq!(split(SplitRule::Line), ["a\r\n", "b\n", "c"]);
+        assert_eq!(split(SplitRule::None), [text]);
+    }
+
+    #[test]
+    fn quotes_open_only_at_the_start_of_a_field() {
+        let splitter = RecordSplitter::new(',');
+        assert_eq!(splitter.split("5\" pipe,x\r\nnext"), ["5\" pipe,x", "next"]);
+        assert_eq!(
+            splitter.split("a,\"b\r\n\"\"c\"\"\",d\r\ne\r\n"),
+            ["a,\"b\r\n\"\"c\"\"\",d", "e"]
+        );
+        assert_eq!(parse_record("a,\"b\r\n\"\"c\"\"\",d", ','), ["a", "b\n\"c\"", "d"]);
+        // TSVではカンマの後の引用符は引用の開始ではない
+        assert_eq!(RecordSplitter::new('\t').split("a,\"b\nc"), ["a,\"b", "c"]);
+    }
+
+    #[test]
+    fn missing_columns_become_empty_cells() {
+        let records = ["a\tb".to_owned(), "c".to_owned()];
+        assert_eq!(transform_table(&records, '\t', &[2, 1], false), ["b\ta", "\tc"]);
+        assert_eq!(transform_table(&records, '\t', &[], true), ["a\tc", "b\t"]);
+    }
+}
@@ -0,0 +1,51 @@
+use once_cell::unsync::*;
+use serde_derive::Deserialize;
+use std::sync::{Arc, Mutex};
+
+// アクティブウィンドウの情報
+#[derive(Debug, Clone, PartialEq, Default, Deserialize)]
+#[serde(default)]
+pub struct WindowInfo {
+    pub title: String,
+    pub process_name: String,
+    pub pid: u32,
+    pub class: String,
+}
+
+// アクティブウィンドウの情報を取得するバックエンド
+pub trait WindowInfoProvider: Send {
+    // フォーカスを持つウィンドウが無い場合はNone
+    fn active_window(&mut self) -> Option<WindowInfo>;
+}
+
+static mut WINDOW_INFO_PROVIDER: Lazy<Mutex<Box<dyn WindowInfoProvider>>> =
+    Lazy::new(|| Mutex::new(crate::platform::default_window_info_provider()));
+
+pub fn set_window_info_provider(provider: Box<dyn WindowInfoProvider>) {
+    let mut p = unsafe { WINDOW_INFO_PROVIDER.lock().unwrap() };
+    *p = provider;
+}
+pub fn active_window() -> Option<WindowInfo> {
+    let mut p = unsafe { WINDOW_INFO_PROVIDER.lock().unwrap() };
+    p.active_window()
+}
+
+// 任意のウィンドウがアクティブであるように振る舞うバックエンド
+// cloneしたものは状態を共有する。
+#[derive(Clone, Default)]
+pub struct MockWindowInfoProvider {
+    current: Arc<Mutex<Option<WindowInfo>>>,
+}
+impl MockWindowInfoProvider {
+    pub fn new() -> Self {
+        MockWindowInfoProvider::default()
+    }
+    pub fn set_active_window(&self, window: Option<WindowInfo>) {
+        *self.current.lock().unwrap() = window;
+    }
+}
+impl WindowInfoProvider for MockWindowInfoProvider {
+    fn active_window(&mut self) -> Option<WindowInfo> {
+        self.current.lock().unwrap().clone()
+    }
+}